@@ -0,0 +1,71 @@
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, Data, DeriveInput, Expr, Field, Ident, Index, LitStr, Member, Token};
+
+use crate::case;
+
+/// Reads `rename` and `rename_all` from `#[serde(...)]`, ignoring every other serde option.
+pub fn serde_option(attrs: &[Attribute], key: &str) -> syn::Result<Option<LitStr>> {
+  let mut value = None;
+  for attr in attrs.iter().filter(|x| x.path().is_ident("serde")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident(key) && meta.input.peek(Token![=]) {
+        value = Some(meta.value()?.parse()?);
+        Ok(())
+      } else {
+        skip(meta)
+      }
+    })?;
+  }
+
+  Ok(value)
+}
+
+fn skip(meta: ParseNestedMeta) -> syn::Result<()> {
+  if meta.input.peek(Token![=]) {
+    meta.value()?.parse::<Expr>()?;
+  } else if meta.input.peek(syn::token::Paren) {
+    meta.parse_nested_meta(skip)?;
+  }
+  Ok(())
+}
+
+/// Returns each variant (or the struct itself as `None`) with the name serde serializes it as.
+pub fn serde_names(input: &DeriveInput) -> syn::Result<Vec<(Option<Ident>, String)>> {
+  if let Some(name) = serde_option(&input.attrs, "rename")? {
+    if !matches!(input.data, Data::Enum(_)) {
+      return Ok(vec![(None, name.value())]);
+    }
+  }
+
+  let variants = match &input.data {
+    Data::Enum(data) => &data.variants,
+    Data::Struct(_) => return Ok(vec![(None, input.ident.to_string())]),
+    Data::Union(_) => {
+      return Err(syn::Error::new_spanned(
+        &input.ident,
+        "unions are not supported",
+      ))
+    }
+  };
+
+  let rename_all = serde_option(&input.attrs, "rename_all")?;
+  variants
+    .iter()
+    .map(|variant| {
+      let name = match (serde_option(&variant.attrs, "rename")?, &rename_all) {
+        (Some(name), _) => name.value(),
+        (None, Some(rule)) => case::rename(&variant.ident.to_string(), &rule.value())
+          .ok_or_else(|| syn::Error::new_spanned(rule, "unknown rename_all rule"))?,
+        (None, None) => variant.ident.to_string(),
+      };
+      Ok((Some(variant.ident.clone()), name))
+    })
+    .collect()
+}
+
+pub fn member(index: usize, field: &Field) -> Member {
+  match &field.ident {
+    Some(ident) => Member::Named(ident.clone()),
+    None => Member::Unnamed(Index::from(index)),
+  }
+}
@@ -0,0 +1,51 @@
+/// Applies a serde `rename_all` rule to a PascalCase variant name.
+pub fn rename(name: &str, rule: &str) -> Option<String> {
+  let renamed = match rule {
+    "lowercase" => name.to_ascii_lowercase(),
+    "UPPERCASE" => name.to_ascii_uppercase(),
+    "PascalCase" => name.to_owned(),
+    "camelCase" => {
+      let mut chars = name.chars();
+      match chars.next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => String::new(),
+      }
+    }
+    "snake_case" => separate(name, '_'),
+    "SCREAMING_SNAKE_CASE" => separate(name, '_').to_ascii_uppercase(),
+    "kebab-case" => separate(name, '-'),
+    "SCREAMING-KEBAB-CASE" => separate(name, '-').to_ascii_uppercase(),
+    _ => return None,
+  };
+
+  Some(renamed)
+}
+
+fn separate(name: &str, separator: char) -> String {
+  let mut separated = String::new();
+  for (i, ch) in name.char_indices() {
+    if i > 0 && ch.is_uppercase() {
+      separated.push(separator);
+    }
+    separated.push(ch.to_ascii_lowercase());
+  }
+  separated
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn should_rename_by_rule() {
+    assert_eq!(rename("TodoCreated", "lowercase").unwrap(), "todocreated");
+    assert_eq!(rename("TodoCreated", "camelCase").unwrap(), "todoCreated");
+    assert_eq!(rename("TodoCreated", "snake_case").unwrap(), "todo_created");
+    assert_eq!(
+      rename("TodoCreated", "SCREAMING_SNAKE_CASE").unwrap(),
+      "TODO_CREATED"
+    );
+    assert_eq!(rename("TodoCreated", "kebab-case").unwrap(), "todo-created");
+    assert!(rename("TodoCreated", "Title Case").is_none());
+  }
+}
@@ -0,0 +1,182 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Field, Fields, Member};
+
+use crate::attrs::member;
+use crate::event::name_body;
+
+pub fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  let name = name_body(&input)?;
+  let aggregate_id = aggregate_id_body(&input)?;
+  let idempotency_key = idempotency_key_body(&input)?.map(|body| {
+    quote! {
+      fn idempotency_key(&self) -> ::core::option::Option<&str> {
+        #body
+      }
+    }
+  });
+
+  Ok(quote! {
+    impl #impl_generics ::geeks_event_sourcing::Command for #ident #ty_generics #where_clause {
+      fn name(&self) -> &'static str {
+        #name
+      }
+
+      fn aggregate_id(&self) -> &str {
+        #aggregate_id
+      }
+
+      #idempotency_key
+    }
+  })
+}
+
+fn aggregate_id_body(input: &DeriveInput) -> syn::Result<TokenStream> {
+  match &input.data {
+    Data::Struct(data) => {
+      let member = aggregate_id_member(&data.fields, &input.ident)?;
+      Ok(quote! { ::core::convert::AsRef::<str>::as_ref(&self.#member) })
+    }
+    Data::Enum(data) => {
+      let arms = data
+        .variants
+        .iter()
+        .map(|variant| {
+          let ident = &variant.ident;
+          let member = aggregate_id_member(&variant.fields, ident)?;
+          Ok(quote! {
+            Self::#ident { #member: aggregate_id, .. } => {
+              ::core::convert::AsRef::<str>::as_ref(aggregate_id)
+            }
+          })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+      Ok(quote! {
+        match self {
+          #(#arms)*
+        }
+      })
+    }
+    Data::Union(_) => Err(syn::Error::new_spanned(
+      &input.ident,
+      "unions are not supported",
+    )),
+  }
+}
+
+/// Returns `None` when no field is marked, keeping the default `idempotency_key`.
+fn idempotency_key_body(input: &DeriveInput) -> syn::Result<Option<TokenStream>> {
+  match &input.data {
+    Data::Struct(data) => {
+      let member = match idempotency_key_member(&data.fields)? {
+        Some(x) => x,
+        None => return Ok(None),
+      };
+      Ok(Some(quote! {
+        ::geeks_event_sourcing::AsIdempotencyKey::as_idempotency_key(&self.#member)
+      }))
+    }
+    Data::Enum(data) => {
+      let mut marked = false;
+      let mut arms = Vec::new();
+      for variant in &data.variants {
+        let ident = &variant.ident;
+        arms.push(match idempotency_key_member(&variant.fields)? {
+          Some(member) => {
+            marked = true;
+            quote! {
+              Self::#ident { #member: key, .. } => {
+                ::geeks_event_sourcing::AsIdempotencyKey::as_idempotency_key(key)
+              }
+            }
+          }
+          None => quote! { Self::#ident { .. } => ::core::option::Option::None, },
+        });
+      }
+      if !marked {
+        return Ok(None);
+      }
+      Ok(Some(quote! {
+        match self {
+          #(#arms)*
+        }
+      }))
+    }
+    Data::Union(_) => Ok(None),
+  }
+}
+
+fn idempotency_key_member(fields: &Fields) -> syn::Result<Option<Member>> {
+  let mut marked = None;
+  for (i, field) in fields.iter().enumerate() {
+    if parse_flags(field)?.idempotency_key {
+      if marked.is_some() {
+        return Err(syn::Error::new_spanned(
+          field,
+          "only one field can be marked as #[command(idempotency_key)]",
+        ));
+      }
+      marked = Some(member(i, field));
+    }
+  }
+
+  Ok(marked)
+}
+
+fn aggregate_id_member(fields: &Fields, owner: &syn::Ident) -> syn::Result<Member> {
+  let members = fields
+    .iter()
+    .enumerate()
+    .map(|(i, field)| (member(i, field), field));
+
+  let mut marked = None;
+  let mut named_id = None;
+  for (member, field) in members {
+    if parse_flags(field)?.aggregate_id {
+      if marked.is_some() {
+        return Err(syn::Error::new_spanned(
+          field,
+          "only one field can be marked as #[command(aggregate_id)]",
+        ));
+      }
+      marked = Some(member);
+    } else if field.ident.as_ref().is_some_and(|x| x == "id") {
+      named_id = Some(member);
+    }
+  }
+
+  marked.or(named_id).ok_or_else(|| {
+    syn::Error::new_spanned(
+      owner,
+      "missing aggregate id: mark a field with #[command(aggregate_id)]",
+    )
+  })
+}
+
+#[derive(Default)]
+struct FieldFlags {
+  aggregate_id: bool,
+  idempotency_key: bool,
+}
+
+fn parse_flags(field: &Field) -> syn::Result<FieldFlags> {
+  let mut flags = FieldFlags::default();
+  for attr in field.attrs.iter().filter(|x| x.path().is_ident("command")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("aggregate_id") {
+        flags.aggregate_id = true;
+        Ok(())
+      } else if meta.path.is_ident("idempotency_key") {
+        flags.idempotency_key = true;
+        Ok(())
+      } else {
+        Err(meta.error("unknown command attribute"))
+      }
+    })?;
+  }
+
+  Ok(flags)
+}
@@ -0,0 +1,230 @@
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_quote, Data, DeriveInput, Field, Fields, LitInt, Member};
+
+use crate::attrs::{member, serde_names, serde_option};
+
+pub fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+  let ident = &input.ident;
+  // Personal data must read back once redacted, which the field types are bound to support.
+  let mut generics = input.generics.clone();
+  for field in personal_data(&input)? {
+    let ty = &field.ty;
+    let bound = quote_spanned! {ty.span()=> #ty: ::geeks_event_sourcing::PersonalData };
+    generics
+      .make_where_clause()
+      .predicates
+      .push(parse_quote!(#bound));
+  }
+  let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+  let schema_version = schema_version(&input)?.map(|version| {
+    quote! { const SCHEMA_VERSION: u32 = #version; }
+  });
+  let name = name_body(&input)?;
+  let personal_data_fields = personal_data_fields_body(&input)?.map(|body| {
+    quote! {
+      fn personal_data_fields(&self) -> &'static [&'static str] {
+        #body
+      }
+    }
+  });
+  let data_subject = data_subject_body(&input)?.map(|body| {
+    quote! {
+      fn data_subject(&self) -> ::core::option::Option<&str> {
+        #body
+      }
+    }
+  });
+
+  Ok(quote! {
+    impl #impl_generics ::geeks_event_sourcing::Event for #ident #ty_generics #where_clause {
+      #schema_version
+
+      fn name(&self) -> &'static str {
+        #name
+      }
+
+      #personal_data_fields
+
+      #data_subject
+    }
+  })
+}
+
+fn schema_version(input: &DeriveInput) -> syn::Result<Option<LitInt>> {
+  let mut version = None;
+  for attr in input.attrs.iter().filter(|x| x.path().is_ident("event")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("schema_version") {
+        version = Some(meta.value()?.parse()?);
+        Ok(())
+      } else {
+        Err(meta.error("unknown event attribute"))
+      }
+    })?;
+  }
+
+  Ok(version)
+}
+
+pub fn name_body(input: &DeriveInput) -> syn::Result<TokenStream> {
+  let names = serde_names(input)?;
+  if let [(None, name)] = names.as_slice() {
+    return Ok(quote! { #name });
+  }
+
+  let arms = names.iter().map(|(variant, name)| {
+    quote! { Self::#variant { .. } => #name, }
+  });
+  Ok(quote! {
+    match self {
+      #(#arms)*
+    }
+  })
+}
+
+/// Fields of a struct, or of each variant with the variant ident.
+fn fields_of(input: &DeriveInput) -> Vec<(Option<&syn::Ident>, &Fields)> {
+  match &input.data {
+    Data::Struct(data) => vec![(None, &data.fields)],
+    Data::Enum(data) => data
+      .variants
+      .iter()
+      .map(|x| (Some(&x.ident), &x.fields))
+      .collect(),
+    Data::Union(_) => Vec::new(),
+  }
+}
+
+/// Fields marked with `#[event(personal_data)]`, across all variants.
+fn personal_data(input: &DeriveInput) -> syn::Result<Vec<&Field>> {
+  let mut marked = Vec::new();
+  for (_, fields) in fields_of(input) {
+    for field in fields {
+      if parse_flags(field)?.personal_data {
+        marked.push(field);
+      }
+    }
+  }
+
+  Ok(marked)
+}
+
+/// Returns `None` when no field is marked, keeping the default `personal_data_fields`.
+fn personal_data_fields_body(input: &DeriveInput) -> syn::Result<Option<TokenStream>> {
+  let mut marked = false;
+  let mut arms = Vec::new();
+  for (variant, fields) in fields_of(input) {
+    let mut names = Vec::new();
+    for field in fields {
+      if !parse_flags(field)?.personal_data {
+        continue;
+      }
+      let name = match (serde_option(&field.attrs, "rename")?, &field.ident) {
+        (Some(name), _) => name.value(),
+        (None, Some(ident)) => ident.to_string(),
+        (None, None) => {
+          return Err(syn::Error::new_spanned(
+            field,
+            "personal data fields must be named",
+          ))
+        }
+      };
+      names.push(name);
+    }
+    marked |= !names.is_empty();
+    arms.push((variant, names));
+  }
+  if !marked {
+    return Ok(None);
+  }
+
+  if let [(None, names)] = arms.as_slice() {
+    return Ok(Some(quote! { &[#(#names),*] }));
+  }
+  let arms = arms.iter().map(|(variant, names)| {
+    quote! { Self::#variant { .. } => &[#(#names),*], }
+  });
+  Ok(Some(quote! {
+    match self {
+      #(#arms)*
+    }
+  }))
+}
+
+/// Returns `None` when no field is marked, keeping the default `data_subject`.
+fn data_subject_body(input: &DeriveInput) -> syn::Result<Option<TokenStream>> {
+  let mut marked = false;
+  let mut arms = Vec::new();
+  for (variant, fields) in fields_of(input) {
+    let member = data_subject_member(fields)?;
+    marked |= member.is_some();
+    arms.push((variant, member));
+  }
+  if !marked {
+    return Ok(None);
+  }
+
+  if let [(None, Some(member))] = arms.as_slice() {
+    return Ok(Some(quote! {
+      ::core::option::Option::Some(::core::convert::AsRef::<str>::as_ref(&self.#member))
+    }));
+  }
+  let arms = arms.iter().map(|(variant, member)| match member {
+    Some(member) => quote! {
+      Self::#variant { #member: subject, .. } => {
+        ::core::option::Option::Some(::core::convert::AsRef::<str>::as_ref(subject))
+      }
+    },
+    None => quote! { Self::#variant { .. } => ::core::option::Option::None, },
+  });
+  Ok(Some(quote! {
+    match self {
+      #(#arms)*
+    }
+  }))
+}
+
+fn data_subject_member(fields: &Fields) -> syn::Result<Option<Member>> {
+  let mut marked = None;
+  for (i, field) in fields.iter().enumerate() {
+    if parse_flags(field)?.data_subject {
+      if marked.is_some() {
+        return Err(syn::Error::new_spanned(
+          field,
+          "only one field can be marked as #[event(data_subject)]",
+        ));
+      }
+      marked = Some(member(i, field));
+    }
+  }
+
+  Ok(marked)
+}
+
+#[derive(Default)]
+struct FieldFlags {
+  personal_data: bool,
+  data_subject: bool,
+}
+
+fn parse_flags(field: &Field) -> syn::Result<FieldFlags> {
+  let mut flags = FieldFlags::default();
+  for attr in field.attrs.iter().filter(|x| x.path().is_ident("event")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("personal_data") {
+        flags.personal_data = true;
+        Ok(())
+      } else if meta.path.is_ident("data_subject") {
+        flags.data_subject = true;
+        Ok(())
+      } else {
+        Err(meta.error("unknown event attribute"))
+      }
+    })?;
+  }
+
+  Ok(flags)
+}
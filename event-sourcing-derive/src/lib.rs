@@ -0,0 +1,35 @@
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+mod attrs;
+mod case;
+mod command;
+mod event;
+
+/// Implements `Event` using the serde name of each variant, so `name()` always matches the
+/// value of `#[serde(tag = "name")]`.
+///
+/// `#[event(schema_version = 2)]` on the type sets `Event::SCHEMA_VERSION`. Fields marked with
+/// `#[event(personal_data)]` are listed by `Event::personal_data_fields` and must implement
+/// `PersonalData`, and a field marked with `#[event(data_subject)]` implements
+/// `Event::data_subject`.
+#[proc_macro_derive(Event, attributes(event))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  event::expand(input)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+/// Implements `Command` using the serde name of each variant.
+///
+/// The aggregate id is read from the field marked with `#[command(aggregate_id)]`, or from the
+/// field named `id` when nothing is marked. A `String` or `Option<String>` field marked with
+/// `#[command(idempotency_key)]` implements `Command::idempotency_key`.
+#[proc_macro_derive(Command, attributes(command))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  command::expand(input)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
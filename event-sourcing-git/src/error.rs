@@ -0,0 +1,32 @@
+use geeks_event_sourcing::CodecError;
+use geeks_git::GitError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GitEventstoreError {
+  #[error("git error: {0}")]
+  GitError(#[from] GitError),
+
+  #[error("codec error: {0}")]
+  CodecError(#[from] CodecError),
+}
+
+impl From<git2::Error> for GitEventstoreError {
+  fn from(e: git2::Error) -> Self {
+    Self::GitError(e.into())
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GitSagaStoreError {
+  #[error("git error: {0}")]
+  GitError(#[from] GitError),
+
+  #[error("json parse error: {0}")]
+  JsonParseError(#[from] serde_json::Error),
+}
+
+impl From<std::io::Error> for GitSagaStoreError {
+  fn from(e: std::io::Error) -> Self {
+    Self::GitError(e.into())
+  }
+}
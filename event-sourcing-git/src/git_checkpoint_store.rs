@@ -0,0 +1,107 @@
+use std::fs::{create_dir_all, read_to_string, rename, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use geeks_event_sourcing::{CheckpointStore, Position};
+use geeks_git::GitError;
+use git2::Repository;
+
+/// Keeps projection checkpoints inside the git directory, so they stay local to the clone and
+/// never end up in snapshot commits.
+pub struct GitCheckpointStore {
+  repo_path: PathBuf,
+}
+
+impl GitCheckpointStore {
+  pub fn new(repo_path: &Path) -> Self {
+    Self {
+      repo_path: repo_path.to_path_buf(),
+    }
+  }
+
+  fn checkpoints_dir(&self) -> Result<PathBuf, GitError> {
+    let repo = Repository::open(&self.repo_path)?;
+    Ok(repo.path().join("geeks").join("checkpoints"))
+  }
+
+  /// Names are encoded, so any name maps to a single file inside the checkpoints directory.
+  fn file_name(name: &str) -> String {
+    URL_SAFE_NO_PAD.encode(name)
+  }
+}
+
+#[async_trait]
+impl CheckpointStore for GitCheckpointStore {
+  type Error = GitError;
+
+  async fn load(&self, name: &str) -> Result<Position, Self::Error> {
+    let file_path = self.checkpoints_dir()?.join(Self::file_name(name));
+    let raw = match read_to_string(file_path) {
+      Ok(x) => x,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+      Err(e) => return Err(e.into()),
+    };
+
+    raw
+      .trim()
+      .parse()
+      .map_err(|_| GitError::Generic(format!("invalid checkpoint for '{}': {}", name, raw)))
+  }
+
+  async fn save(&self, name: &str, position: Position) -> Result<(), Self::Error> {
+    let dir = self.checkpoints_dir()?;
+    create_dir_all(&dir)?;
+
+    let file_name = Self::file_name(name);
+    let tmp_path = dir.join(format!(".{}.tmp", file_name));
+    write(&tmp_path, position.to_string())?;
+    rename(tmp_path, dir.join(file_name))?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use geeks_event_sourcing::CheckpointStore;
+  use geeks_git::{get_status, StatusType};
+
+  use geeks_git_testing::FixtureRepository;
+
+  use crate::GitCheckpointStore;
+
+  #[tokio::test]
+  async fn should_save_and_load_checkpoints() {
+    let fixture = FixtureRepository::setup();
+    let checkpoints = GitCheckpointStore::new(&fixture.path);
+    assert_eq!(checkpoints.load("todos").await.unwrap(), 0);
+
+    checkpoints.save("todos", 3).await.unwrap();
+    checkpoints.save("todos", 5).await.unwrap();
+    checkpoints.save("counts", 1).await.unwrap();
+
+    assert_eq!(checkpoints.load("todos").await.unwrap(), 5);
+    assert_eq!(checkpoints.load("counts").await.unwrap(), 1);
+    assert!(get_status(&fixture.path, StatusType::Both)
+      .unwrap()
+      .is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_keep_any_name_inside_checkpoints_dir() {
+    let fixture = FixtureRepository::setup();
+    let checkpoints = GitCheckpointStore::new(&fixture.path);
+    let config = fixture.path.join(".git").join("config");
+    let config_before = std::fs::read_to_string(&config).unwrap();
+
+    checkpoints.save("../config", 7).await.unwrap();
+    checkpoints.save("a/b", 2).await.unwrap();
+
+    assert_eq!(checkpoints.load("../config").await.unwrap(), 7);
+    assert_eq!(checkpoints.load("a/b").await.unwrap(), 2);
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), config_before);
+  }
+}
@@ -1,123 +1,593 @@
-use std::marker::PhantomData;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
-use geeks_event_sourcing::{Event, Eventstore, PersistedEvent, VersionSelect};
-use geeks_git::{commit, CommitInfo, CommitMessage, CommitReader, GitError};
-use git2::Repository;
+use futures::stream::{self, BoxStream, StreamExt};
+use geeks_event_sourcing::{
+  AppendError, Event, EventBus, EventCodec, EventHeader, EventPublisher, Eventstore,
+  PersistedEvent, Position, PositionedEvent, Timestamp, Version, VersionSelect,
+};
+use geeks_git::{
+  commit, commit_on_parent, find_head, CommitInfo, CommitMessage, CommitReader, GitError,
+};
+use git2::{Oid, Repository};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::{from_str, to_string};
+use tokio::sync::mpsc;
+use tokio::task;
 
-use crate::SNAPSHOT_MSG;
+use crate::{GitEventstoreError, SNAPSHOT_MSG};
 
 pub const EVENT_MSG: &str = "[event]";
 
+/// Number of decoded events buffered ahead of a slow stream consumer.
+const STREAM_BUFFER_SIZE: usize = 64;
+
+type EventResult<T> = Result<PersistedEvent<T>, GitEventstoreError>;
+
+type HeaderResult = Result<(EventHeader, CommitInfo), GitEventstoreError>;
+
+#[derive(Clone)]
 pub struct GitEventstore<T>
 where
   T: Event,
 {
   repo_path: PathBuf,
-  _event: PhantomData<T>,
+  codec: EventCodec<T>,
+  event_bus: EventBus<T>,
+  /// Number of events up to a commit, so publishing only counts the commits added since.
+  last_position: Arc<Mutex<Option<(Oid, Position)>>>,
 }
 
 impl<T> GitEventstore<T>
 where
-  T: Event + Serialize + DeserializeOwned,
+  T: Event + Serialize + DeserializeOwned + 'static,
 {
   pub fn new(repo_path: &Path) -> Self {
     Self {
       repo_path: repo_path.to_path_buf(),
-      _event: PhantomData::default(),
+      codec: EventCodec::default(),
+      event_bus: EventBus::default(),
+      last_position: Arc::default(),
     }
   }
 
-  fn event_to_commit_message(persisted: PersistedEvent<T>) -> CommitMessage {
-    CommitMessage {
+  #[must_use]
+  pub fn with_codec(self, codec: EventCodec<T>) -> Self {
+    Self { codec, ..self }
+  }
+
+  fn event_to_commit_message(
+    &self,
+    persisted: PersistedEvent<T>,
+  ) -> Result<CommitMessage, GitEventstoreError> {
+    Ok(CommitMessage {
       subject: format!(
         "{prefix} {event_name}",
         prefix = EVENT_MSG,
         event_name = persisted.event.name()
       ),
-      body: to_string(&persisted).unwrap(),
-    }
+      body: self.codec.encode(&persisted)?,
+    })
   }
 
-  fn commit_to_event(commit: CommitInfo) -> Option<PersistedEvent<T>> {
+  fn commit_to_event(
+    &self,
+    commit: CommitInfo,
+  ) -> Option<Result<PersistedEvent<T>, GitEventstoreError>> {
     if !commit.message.subject.contains(EVENT_MSG) {
       return None;
     }
 
-    match from_str(commit.message.body.trim()) {
-      Ok(x) => Some(x),
-      Err(_) => None,
+    Some(self.decode_commit(&commit))
+  }
+
+  fn decode_commit(&self, commit: &CommitInfo) -> EventResult<T> {
+    Ok(self.codec.decode(commit.message.body.trim())?)
+  }
+
+  fn read_events<'a>(
+    &'a self,
+    reader: CommitReader<'a>,
+  ) -> impl Iterator<Item = Result<PersistedEvent<T>, GitEventstoreError>> + 'a {
+    reader.filter_map(move |commit| match commit {
+      Ok(commit) => self.commit_to_event(commit),
+      Err(e) => Some(Err(e.into())),
+    })
+  }
+
+  /// Like `read_events`, but only reads the envelope of each event, so commits can be skipped
+  /// without decrypting and upcasting them. Use `decode_commit` on the ones that are kept.
+  fn read_headers<'a>(
+    &'a self,
+    reader: CommitReader<'a>,
+  ) -> impl Iterator<Item = HeaderResult> + 'a {
+    reader.filter_map(move |commit| {
+      let commit = match commit {
+        Ok(x) => x,
+        Err(e) => return Some(Err(e.into())),
+      };
+      if !commit.message.subject.contains(EVENT_MSG) {
+        return None;
+      }
+
+      Some(
+        self
+          .codec
+          .decode_header(commit.message.body.trim())
+          .map(|header| (header, commit))
+          .map_err(GitEventstoreError::from),
+      )
+    })
+  }
+
+  fn stream_version(
+    &self,
+    repo: &Repository,
+    head: Option<Oid>,
+    aggregate_id: &str,
+  ) -> Result<Version, GitEventstoreError> {
+    let head = match head {
+      Some(x) => x,
+      None => return Ok(0),
+    };
+    let reader = CommitReader::new(repo)?.start_on_oid(head);
+    for header in self.read_headers(reader) {
+      let (header, _) = header?;
+      if header.aggregate_id == aggregate_id {
+        return Ok(header.version);
+      }
     }
+
+    Ok(0)
   }
 
-  pub async fn read_until_snapshot(&self) -> Result<Vec<PersistedEvent<T>>, GitError> {
-    let repo = Repository::open(&self.repo_path)?;
-    let mut events: Vec<_> = CommitReader::new(&repo)?
-      .start_on_head()
-      .end_when(|x| x.message.subject.contains(SNAPSHOT_MSG))
-      .flatten()
-      .filter_map(GitEventstore::commit_to_event)
+  /// Publishes freshly committed events. A failure only delays subscribers, which catch up
+  /// from the store, so it is not reported to the appending caller.
+  fn publish(&self, head: Oid, events: Vec<PersistedEvent<T>>) {
+    let last_position = match self.position_of(head) {
+      Ok(x) => x,
+      Err(_) => return,
+    };
+
+    let first_position = last_position + 1 - events.len() as Position;
+    let events = events
+      .into_iter()
+      .enumerate()
+      .map(|(index, persisted)| PositionedEvent {
+        position: first_position + index as Position,
+        persisted,
+      })
       .collect();
+    self.event_bus.publish(events);
+  }
+
+  /// Counts the events up to `head`, starting from the last counted commit when `head`
+  /// descends from it.
+  fn position_of(&self, head: Oid) -> Result<Position, GitEventstoreError> {
+    let repo = Repository::open(&self.repo_path)?;
+    let mut reader = CommitReader::new(&repo)?.start_on_oid(head);
+    let mut last_position: Position = 0;
+    let cached = *self.last_position.lock().unwrap();
+    if let Some((oid, position)) = cached {
+      if oid == head || repo.graph_descendant_of(head, oid)? {
+        reader = reader.hide_oid(oid);
+        last_position = position;
+      }
+    }
+
+    for commit in reader {
+      if commit?.message.subject.contains(EVENT_MSG) {
+        last_position += 1;
+      }
+    }
+    *self.last_position.lock().unwrap() = Some((head, last_position));
+
+    Ok(last_position)
+  }
+
+  pub async fn read_until_snapshot(&self) -> Result<Vec<PersistedEvent<T>>, GitEventstoreError> {
+    self
+      .stream_until_snapshot()
+      .collect::<Vec<_>>()
+      .await
+      .into_iter()
+      .collect()
+  }
+
+  /// Streams events committed after the latest snapshot commit, oldest first.
+  pub fn stream_until_snapshot(&self) -> BoxStream<'static, EventResult<T>> {
+    self.spawn_stream(|this, emit| this.walk_until_snapshot(emit))
+  }
+
+  /// Walks the history on a blocking thread and sends decoded events through a bounded channel,
+  /// so the walk pauses while the consumer is behind. The walk starts when the stream is first
+  /// polled, which must happen within a Tokio runtime.
+  fn spawn_stream<F>(&self, walk: F) -> BoxStream<'static, EventResult<T>>
+  where
+    F: FnOnce(&Self, &mut dyn FnMut(PersistedEvent<T>) -> bool) -> Result<(), GitEventstoreError>
+      + Send
+      + 'static,
+  {
+    let this = self.clone();
+    stream::once(async move {
+      let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+      task::spawn_blocking(move || {
+        let result = walk(&this, &mut |event| sender.blocking_send(Ok(event)).is_ok());
+        if let Err(e) = result {
+          let _ = sender.blocking_send(Err(e));
+        }
+      });
+
+      stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|x| (x, receiver))
+      })
+    })
+    .flatten()
+    .boxed()
+  }
+
+  fn walk_until_snapshot(
+    &self,
+    emit: &mut dyn FnMut(PersistedEvent<T>) -> bool,
+  ) -> Result<(), GitEventstoreError> {
+    let repo = Repository::open(&self.repo_path)?;
+    if find_head(&repo)?.is_none() {
+      return Ok(());
+    }
+
+    for event in self.read_events(Self::since_snapshot(&repo)?) {
+      if !emit(event?) {
+        break;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Reads commits after the latest snapshot commit, oldest first.
+  fn since_snapshot(repo: &Repository) -> Result<CommitReader<'_>, GitEventstoreError> {
+    let mut reader = CommitReader::new(repo)?.start_on_head().reversed();
+    for commit in CommitReader::new(repo)?.start_on_head() {
+      let commit = commit?;
+      if commit.message.subject.contains(SNAPSHOT_MSG) {
+        reader = reader.hide_oid(commit.id);
+        break;
+      }
+    }
+
+    Ok(reader)
+  }
+
+  /// Lists ids from commits after `after`, or from every commit when `after` is `None`.
+  fn read_aggregate_ids(&self, after: Option<Oid>) -> Result<Vec<String>, GitEventstoreError> {
+    let repo = Repository::open(&self.repo_path)?;
+    if find_head(&repo)?.is_none() {
+      return Ok(Vec::new());
+    }
+
+    let mut reader = CommitReader::new(&repo)?.start_on_head().reversed();
+    if let Some(after) = after {
+      reader = reader.hide_oid(after);
+    }
+    let mut ids = Vec::new();
+    let mut seen = HashSet::new();
+    for header in self.read_headers(reader) {
+      let (header, _) = header?;
+      if seen.insert(header.aggregate_id.clone()) {
+        ids.push(header.aggregate_id);
+      }
+    }
+
+    Ok(ids)
+  }
+
+  fn walk_stream(
+    &self,
+    aggregate_id: &str,
+    select: VersionSelect,
+    emit: &mut dyn FnMut(PersistedEvent<T>) -> bool,
+  ) -> Result<(), GitEventstoreError> {
+    // The tail can only be known by walking from the newest commit, and it is bounded anyway.
+    if let VersionSelect::Last(_) = select {
+      for event in self.read_selected(aggregate_id, select)? {
+        if !emit(event) {
+          break;
+        }
+      }
+      return Ok(());
+    }
+
+    let repo = Repository::open(&self.repo_path)?;
+    if find_head(&repo)?.is_none() {
+      return Ok(());
+    }
+
+    // Versions of an aggregate may be appended out of order, so a selection without lower bound
+    // reads every commit. Walk it from the oldest one to emit events as they are found.
+    if let VersionSelect::UpTo(_) = select {
+      let reader = CommitReader::new(&repo)?.start_on_head().reversed();
+      for header in self.read_headers(reader) {
+        let (header, commit) = header?;
+        if header.aggregate_id == aggregate_id
+          && select.contains(header.version)
+          && !emit(self.decode_commit(&commit)?)
+        {
+          break;
+        }
+      }
+      return Ok(());
+    }
+
+    // Every other selection is walked from the newest commit and ends like `read_selected`. Only
+    // the selected commits are kept, and they are decoded as they are emitted.
+    let mut selected = Vec::new();
+    for header in self.read_headers(CommitReader::new(&repo)?.start_on_head()) {
+      let (header, commit) = header?;
+      if header.aggregate_id != aggregate_id {
+        continue;
+      }
+      if select.contains(header.version) {
+        selected.push(commit);
+      }
+      if is_walk_done(select, header.version, selected.len()) {
+        break;
+      }
+    }
+
+    for commit in selected.iter().rev() {
+      if !emit(self.decode_commit(commit)?) {
+        break;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn read_selected(
+    &self,
+    aggregate_id: &str,
+    select: VersionSelect,
+  ) -> Result<Vec<PersistedEvent<T>>, GitEventstoreError> {
+    let mut events = Vec::new();
+    if select == VersionSelect::Last(0) {
+      return Ok(events);
+    }
+
+    let repo = Repository::open(&self.repo_path)?;
+    let reader = CommitReader::new(&repo)?.start_on_head();
+    // Commits are walked from the newest, so versions of the aggregate only decrease and the
+    // walk can stop as soon as the selection is complete.
+    for header in self.read_headers(reader) {
+      let (header, commit) = header?;
+      if header.aggregate_id != aggregate_id {
+        continue;
+      }
+
+      let version = header.version;
+      if select.contains(version) {
+        events.push(self.decode_commit(&commit)?);
+      }
+      if is_walk_done(select, version, events.len()) {
+        break;
+      }
+    }
 
     events.reverse();
     Ok(events)
   }
 }
 
+/// Whether a walk from the newest commit has found every event of `select`, given the version
+/// of the aggregate just read and the number of events selected so far.
+fn is_walk_done(select: VersionSelect, version: Version, selected: usize) -> bool {
+  match select {
+    VersionSelect::Last(n) => selected as u64 >= n,
+    _ => select.lower_bound().is_some_and(|from| version <= from),
+  }
+}
+
 #[async_trait]
 impl<T> Eventstore for GitEventstore<T>
 where
-  T: Event + Serialize + DeserializeOwned,
+  T: Event + Serialize + DeserializeOwned + 'static,
 {
   type Event = T;
-  type Error = GitError;
+  type Error = GitEventstoreError;
 
   async fn read(
     &self,
     aggregate_id: String,
     select: VersionSelect,
   ) -> Result<Vec<PersistedEvent<Self::Event>>, Self::Error> {
-    let repo = Repository::open(&self.repo_path)?;
-    let mut events: Vec<_> = CommitReader::new(&repo)?
-      .start_on_head()
-      .flatten()
-      .filter_map(GitEventstore::commit_to_event)
-      .filter(|event| event.aggregate_id == aggregate_id)
-      .filter(|event| match select {
-        VersionSelect::All => true,
-        VersionSelect::From(v) => event.version >= v,
-      })
-      .collect();
+    self.read_selected(&aggregate_id, select)
+  }
 
-    events.reverse();
-    Ok(events)
+  fn stream(
+    &self,
+    aggregate_id: String,
+    select: VersionSelect,
+  ) -> BoxStream<'_, Result<PersistedEvent<Self::Event>, Self::Error>> {
+    self.spawn_stream(move |this, emit| this.walk_stream(&aggregate_id, select, emit))
   }
 
   async fn append(&self, events: Vec<PersistedEvent<Self::Event>>) -> Result<(), Self::Error> {
+    // Positions are only computed when somebody listens, as it requires walking the history.
+    let published = self.event_bus.has_subscribers().then(|| events.clone());
     let commit_messages = events
       .into_iter()
-      .map(GitEventstore::event_to_commit_message);
+      .map(|x| self.event_to_commit_message(x))
+      .collect::<Result<Vec<_>, _>>()?;
 
+    let mut head = None;
     for message in commit_messages {
-      commit(&self.repo_path, message)?;
+      head = Some(commit(&self.repo_path, message)?);
+    }
+
+    if let (Some(head), Some(events)) = (head, published) {
+      self.publish(head, events);
     }
 
     Ok(())
   }
+
+  async fn append_expected(
+    &self,
+    aggregate_id: String,
+    expected: Version,
+    events: Vec<PersistedEvent<Self::Event>>,
+  ) -> Result<(), AppendError<Self::Error>> {
+    AppendError::check_events(&aggregate_id, expected, &events)?;
+    let published = self.event_bus.has_subscribers().then(|| events.clone());
+    let commit_messages = events
+      .into_iter()
+      .map(|x| self.event_to_commit_message(x))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(AppendError::EventstoreError)?;
+
+    // HEAD may move because of an unrelated aggregate, so retry until the check and the commit
+    // happen on the same parent.
+    loop {
+      let repo =
+        Repository::open(&self.repo_path).map_err(|e| AppendError::EventstoreError(e.into()))?;
+      let head = find_head(&repo).map_err(|e| AppendError::EventstoreError(e.into()))?;
+      let actual = self
+        .stream_version(&repo, head, &aggregate_id)
+        .map_err(AppendError::EventstoreError)?;
+      if actual != expected {
+        return Err(AppendError::VersionConflict {
+          aggregate_id,
+          expected,
+          actual,
+        });
+      }
+
+      match commit_on_parent(&self.repo_path, head, &commit_messages) {
+        Ok(new_head) => {
+          if let (Some(new_head), Some(events)) = (new_head, published) {
+            self.publish(new_head, events);
+          }
+          return Ok(());
+        }
+        Err(GitError::HeadMoved) => continue,
+        Err(e) => return Err(AppendError::EventstoreError(e.into())),
+      }
+    }
+  }
+
+  async fn read_all(
+    &self,
+    after: Position,
+  ) -> Result<Vec<PositionedEvent<Self::Event>>, Self::Error> {
+    let repo = Repository::open(&self.repo_path)?;
+    if find_head(&repo)?.is_none() {
+      return Ok(Vec::new());
+    }
+
+    let reader = CommitReader::new(&repo)?.start_on_head();
+    let mut events = self.read_events(reader).collect::<Result<Vec<_>, _>>()?;
+    events.reverse();
+
+    let events = events
+      .into_iter()
+      .enumerate()
+      .skip(after as usize)
+      .map(|(index, persisted)| PositionedEvent {
+        position: index as Position + 1,
+        persisted,
+      })
+      .collect();
+
+    Ok(events)
+  }
+
+  /// Only reads the envelope of each event.
+  async fn aggregate_ids(&self) -> Result<Vec<String>, Self::Error> {
+    self.read_aggregate_ids(None)
+  }
+
+  /// The head commit, so a root loaded now never needs to list aggregates behind it again.
+  async fn snapshot_marker(&self) -> Result<Option<String>, Self::Error> {
+    let repo = Repository::open(&self.repo_path)?;
+    Ok(find_head(&repo)?.map(|x| x.to_string()))
+  }
+
+  /// Lists the aggregates with events committed after the `marker` commit, or every aggregate
+  /// when `marker` is not a commit of this repository.
+  async fn aggregate_ids_since_snapshot(&self, marker: &str) -> Result<Vec<String>, Self::Error> {
+    let repo = Repository::open(&self.repo_path)?;
+    let after = Oid::from_str(marker)
+      .ok()
+      .filter(|oid| repo.find_commit(*oid).is_ok());
+    self.read_aggregate_ids(after)
+  }
+
+  /// Walks from the newest commit and stops at the first event of the aggregate recorded at
+  /// or before `at`, like the default implementation with `metadata.recorded_at`.
+  async fn version_at(&self, aggregate_id: String, at: Timestamp) -> Result<Version, Self::Error> {
+    let repo = Repository::open(&self.repo_path)?;
+    if find_head(&repo)?.is_none() {
+      return Ok(0);
+    }
+
+    let reader = CommitReader::new(&repo)?.start_on_head();
+    for header in self.read_headers(reader) {
+      let (header, _) = header?;
+      if header.aggregate_id == aggregate_id && header.metadata.recorded_at.is_none_or(|t| t <= at)
+      {
+        return Ok(header.version);
+      }
+    }
+
+    Ok(0)
+  }
+}
+
+impl<T> EventPublisher for GitEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned + 'static,
+{
+  fn event_bus(&self) -> &EventBus<T> {
+    &self.event_bus
+  }
 }
 
 #[cfg(test)]
 mod tests {
-  use geeks_event_sourcing::testing::{TodoEvent, TodoStatus};
-  use geeks_event_sourcing::{Event, Eventstore, PersistedEvent, VersionSelect};
+  use std::cell::RefCell;
+  use std::sync::Arc;
+
+  use futures::StreamExt;
+  use geeks_event_sourcing::testing::{
+    run_eventstore_suite, InMemoryKeyStore, InMemorySnapshot, Todo, TodoCommand, TodoEvent,
+    TodoStatus,
+  };
+  use geeks_event_sourcing::{
+    load_aggregate, subscribe, AggregateRoot, AppendError, Event, EventCodec, Eventstore,
+    FsSnapshot, KeyStore, Metadata, PersistedEvent, Snapshot, SubscriptionFilter, VersionSelect,
+    REDACTED,
+  };
 
   use geeks_git_testing::FixtureRepository;
 
+  use geeks_git::{commit, get_head_commit, CommitMessage};
+  use git2::Repository;
+
   use crate::git_eventstore::GitEventstore;
+  use crate::{GitEventstoreError, SNAPSHOT_MSG};
+
+  #[tokio::test]
+  async fn should_behave_like_an_eventstore() {
+    let fixtures = RefCell::new(Vec::new());
+    run_eventstore_suite(|| {
+      let fixture = FixtureRepository::setup();
+      let eventstore = GitEventstore::new(&fixture.path);
+      fixtures.borrow_mut().push(fixture);
+      eventstore
+    })
+    .await;
+  }
 
   #[tokio::test]
   async fn should_read_events() {
@@ -138,11 +608,13 @@ mod tests {
           aggregate_id: "todo1".to_string(),
           version: 2,
           event: event2,
+          metadata: Metadata::default(),
         },
         PersistedEvent {
           aggregate_id: "todo1".to_string(),
           version: 1,
           event: event1,
+          metadata: Metadata::default(),
         },
       ])
       .await
@@ -156,4 +628,594 @@ mod tests {
     assert_eq!(events[0].event.name(), "TodoTitleUpdated");
     assert_eq!(events[1].event.name(), "TodoCreated");
   }
+
+  #[tokio::test]
+  async fn should_append_with_expected_version() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let created = PersistedEvent {
+      aggregate_id: "todo1".to_string(),
+      version: 1,
+      event: TodoEvent::TodoCreated {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: TodoStatus::Todo,
+      },
+      metadata: Metadata::default(),
+    };
+    let updated = PersistedEvent {
+      aggregate_id: "todo1".to_string(),
+      version: 2,
+      event: TodoEvent::TodoTitleUpdated {
+        title: "Eat pizza".to_string(),
+      },
+      metadata: Metadata::default(),
+    };
+    let other = PersistedEvent {
+      aggregate_id: "todo2".to_string(),
+      version: 1,
+      event: TodoEvent::TodoTitleUpdated {
+        title: "Other".to_string(),
+      },
+      metadata: Metadata::default(),
+    };
+
+    eventstore
+      .append_expected("todo1".to_string(), 0, vec![created])
+      .await
+      .unwrap();
+    eventstore
+      .append_expected("todo2".to_string(), 0, vec![other])
+      .await
+      .unwrap();
+    eventstore
+      .append_expected("todo1".to_string(), 1, vec![updated.clone()])
+      .await
+      .unwrap();
+
+    let err = eventstore
+      .append_expected("todo1".to_string(), 1, vec![updated])
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      AppendError::VersionConflict {
+        expected: 1,
+        actual: 2,
+        ..
+      }
+    ));
+
+    let events = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn should_keep_metadata_in_commit_body() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let persisted = PersistedEvent {
+      aggregate_id: "todo1".to_string(),
+      version: 1,
+      event: TodoEvent::TodoTitleUpdated {
+        title: "Eat pizza".to_string(),
+      },
+      metadata: Metadata {
+        recorded_at: Some(1652000000),
+        actor: Some("user1".to_string()),
+        correlation_id: Some("request1".to_string()),
+        causation_id: Some("command1".to_string()),
+        idempotency_key: Some("retry1".to_string()),
+        extra: [("client".to_string(), "cli".to_string())].into(),
+      },
+    };
+    eventstore.append(vec![persisted.clone()]).await.unwrap();
+
+    let events = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(events, vec![persisted]);
+  }
+
+  #[test]
+  fn should_read_commit_body_without_metadata() {
+    let fixture = FixtureRepository::setup();
+    commit(
+      &fixture.path,
+      CommitMessage {
+        subject: "[event] TodoTitleUpdated".to_string(),
+        body: r#"{"aggregate_id":"todo1","version":1,"event":{"name":"TodoTitleUpdated","title":"Eat pizza"}}"#.to_string(),
+      },
+    )
+    .unwrap();
+
+    let repo = Repository::open(&fixture.path).unwrap();
+    let commit = get_head_commit(&repo).unwrap();
+    let eventstore = GitEventstore::<TodoEvent>::new(&fixture.path);
+    let event = eventstore.commit_to_event(commit).unwrap().unwrap();
+    assert_eq!(event.metadata, Metadata::default());
+  }
+
+  #[tokio::test]
+  async fn should_fail_to_read_undecodable_events() {
+    let fixture = FixtureRepository::setup();
+    commit(
+      &fixture.path,
+      CommitMessage {
+        subject: "[event] TodoRemoved".to_string(),
+        body: r#"{"aggregate_id":"todo1","version":1,"event":{"name":"TodoRemoved"}}"#.to_string(),
+      },
+    )
+    .unwrap();
+
+    let eventstore = GitEventstore::<TodoEvent>::new(&fixture.path);
+    let err = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, GitEventstoreError::CodecError(_)));
+  }
+
+  #[tokio::test]
+  async fn should_read_all_events_in_commit_order() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    assert!(eventstore.read_all(0).await.unwrap().is_empty());
+
+    let events: Vec<_> = ["todo1", "todo2", "todo1"]
+      .iter()
+      .enumerate()
+      .map(|(i, id)| PersistedEvent {
+        aggregate_id: id.to_string(),
+        version: i as u64 + 1,
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", i),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events[..2].to_vec()).await.unwrap();
+    commit(&fixture.path, SNAPSHOT_MSG).unwrap();
+    eventstore.append(events[2..].to_vec()).await.unwrap();
+
+    let all = eventstore.read_all(0).await.unwrap();
+    let positions: Vec<_> = all.iter().map(|x| x.position).collect();
+    assert_eq!(positions, vec![1, 2, 3]);
+    assert_eq!(all[1].persisted, events[1]);
+
+    let rest = eventstore.read_all(1).await.unwrap();
+    assert_eq!(rest.len(), 2);
+    assert_eq!(rest[0].position, 2);
+    assert_eq!(rest[1].persisted, events[2]);
+  }
+
+  #[tokio::test]
+  async fn should_publish_appended_events_to_subscribers() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events: Vec<_> = (1..=3)
+      .map(|version| PersistedEvent {
+        aggregate_id: "todo1".to_string(),
+        version,
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", version),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events[..1].to_vec()).await.unwrap();
+
+    let subscription = subscribe(&eventstore, SubscriptionFilter::default(), 0);
+    futures::pin_mut!(subscription);
+    assert_eq!(subscription.next().await.unwrap().unwrap().position, 1);
+
+    eventstore
+      .append_expected("todo1".to_string(), 1, events[1..].to_vec())
+      .await
+      .unwrap();
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 2);
+    assert_eq!(event.persisted, events[1]);
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 3);
+    assert_eq!(event.persisted, events[2]);
+
+    // Commits of another writer are counted when positioning the next published events.
+    let other = PersistedEvent {
+      aggregate_id: "todo2".to_string(),
+      ..events[0].clone()
+    };
+    GitEventstore::new(&fixture.path)
+      .append(vec![other.clone()])
+      .await
+      .unwrap();
+    eventstore
+      .append_expected(
+        "todo1".to_string(),
+        3,
+        vec![PersistedEvent {
+          version: 4,
+          ..events[2].clone()
+        }],
+      )
+      .await
+      .unwrap();
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 4);
+    assert_eq!(event.persisted, other);
+    assert_eq!(subscription.next().await.unwrap().unwrap().position, 5);
+  }
+
+  #[tokio::test]
+  async fn should_read_selected_versions_without_walking_whole_history() {
+    let fixture = FixtureRepository::setup();
+    // Undecodable, so reading fails whenever the walk reaches the first commit.
+    commit(
+      &fixture.path,
+      CommitMessage {
+        subject: "[event] TodoRemoved".to_string(),
+        body: r#"{"aggregate_id":"todo1","version":0,"event":{"name":"TodoRemoved"}}"#.to_string(),
+      },
+    )
+    .unwrap();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events: Vec<_> = (1..=5)
+      .map(|version| PersistedEvent {
+        aggregate_id: "todo1".to_string(),
+        version,
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", version),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events).await.unwrap();
+
+    let eventstore = &eventstore;
+    let read = |select| async move {
+      let events = eventstore.read("todo1".to_string(), select).await.unwrap();
+      events.iter().map(|x| x.version).collect::<Vec<_>>()
+    };
+    assert_eq!(read(VersionSelect::From(4)).await, vec![4, 5]);
+    assert_eq!(read(VersionSelect::Range(2, 4)).await, vec![2, 3, 4]);
+    assert_eq!(read(VersionSelect::Last(2)).await, vec![4, 5]);
+    assert!(read(VersionSelect::Last(0)).await.is_empty());
+    assert!(eventstore
+      .read("todo1".to_string(), VersionSelect::UpTo(2))
+      .await
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn should_resolve_timestamps_with_recorded_times() {
+    // Commit times are unrelated to when the events were recorded, e.g. after a rebase.
+    let fixture = FixtureRepository::setup_with_script(
+      r#"
+    export GIT_COMMITTER_DATE="1700000000 +0000"
+    git commit --allow-empty -m "[event] TodoCreated" -m '{"aggregate_id":"todo1","version":1,"event":{"name":"TodoCreated","id":"todo1","title":"Drink coffee","status":"todo"},"metadata":{"recorded_at":1600001000}}'
+    git commit --allow-empty -m "[event] TodoCreated" -m '{"aggregate_id":"todo2","version":1,"event":{"name":"TodoCreated","id":"todo2","title":"Sleep","status":"todo"},"metadata":{"recorded_at":1600002000}}'
+    git commit --allow-empty -m "[event] TodoTitleUpdated" -m '{"aggregate_id":"todo1","version":2,"event":{"name":"TodoTitleUpdated","title":"Eat pizza"},"metadata":{"recorded_at":1600003000}}'
+    "#,
+    );
+    let eventstore = GitEventstore::<TodoEvent>::new(&fixture.path);
+
+    let version_at = |at| eventstore.version_at("todo1".to_string(), at);
+    assert_eq!(version_at(1600000500).await.unwrap(), 0);
+    assert_eq!(version_at(1600002500).await.unwrap(), 1);
+    assert_eq!(version_at(1600003000).await.unwrap(), 2);
+
+    assert_eq!(eventstore.position_at(1600000500).await.unwrap(), 0);
+    assert_eq!(eventstore.position_at(1600002000).await.unwrap(), 2);
+    assert_eq!(eventstore.position_at(1600009000).await.unwrap(), 3);
+  }
+
+  #[tokio::test]
+  async fn should_stream_events_oldest_first() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events: Vec<_> = (1..=4)
+      .map(|version| PersistedEvent {
+        aggregate_id: "todo1".to_string(),
+        version,
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", version),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events[..2].to_vec()).await.unwrap();
+    commit(&fixture.path, SNAPSHOT_MSG).unwrap();
+    eventstore.append(events[2..].to_vec()).await.unwrap();
+
+    let streamed: Vec<_> = eventstore
+      .stream("todo1".to_string(), VersionSelect::All)
+      .map(|x| x.unwrap())
+      .collect()
+      .await;
+    assert_eq!(streamed, events);
+
+    let versions: Vec<_> = eventstore
+      .stream("todo1".to_string(), VersionSelect::Range(2, 3))
+      .map(|x| x.unwrap().version)
+      .collect()
+      .await;
+    assert_eq!(versions, vec![2, 3]);
+
+    let versions: Vec<_> = eventstore
+      .stream("todo1".to_string(), VersionSelect::Last(1))
+      .map(|x| x.unwrap().version)
+      .collect()
+      .await;
+    assert_eq!(versions, vec![4]);
+
+    let unsaved = eventstore.read_until_snapshot().await.unwrap();
+    assert_eq!(unsaved, events[2..].to_vec());
+  }
+
+  #[tokio::test]
+  async fn should_stream_from_version_without_walking_whole_history() {
+    let fixture = FixtureRepository::setup();
+    // Not even an envelope, so any walk reaching the first commit fails.
+    commit(
+      &fixture.path,
+      CommitMessage {
+        subject: "[event] TodoRemoved".to_string(),
+        body: "not an event".to_string(),
+      },
+    )
+    .unwrap();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events: Vec<_> = (1..=50u64)
+      .map(|version| PersistedEvent {
+        aggregate_id: if version % 2 == 0 { "todo1" } else { "todo2" }.to_string(),
+        version: version.div_ceil(2),
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", version),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events).await.unwrap();
+
+    let eventstore = &eventstore;
+    let stream = |select| async move {
+      eventstore
+        .stream("todo1".to_string(), select)
+        .map(|x| x.map(|x| x.version))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+    };
+    assert_eq!(
+      stream(VersionSelect::From(23)).await.unwrap(),
+      vec![23, 24, 25]
+    );
+    assert_eq!(
+      stream(VersionSelect::Range(20, 21)).await.unwrap(),
+      vec![20, 21]
+    );
+    // Without lower bound, any version may still be found further back.
+    assert!(stream(VersionSelect::All).await.is_err());
+    assert!(stream(VersionSelect::UpTo(2)).await.is_err());
+    assert!(eventstore.read_all(0).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn should_stream_and_read_out_of_order_versions_alike() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events = [3, 1, 4, 2]
+      .into_iter()
+      .map(|version| PersistedEvent {
+        aggregate_id: "todo1".to_string(),
+        version,
+        event: TodoEvent::TodoTitleUpdated {
+          title: format!("title {}", version),
+        },
+        metadata: Metadata::default(),
+      })
+      .collect();
+    eventstore.append(events).await.unwrap();
+
+    let selects = [
+      VersionSelect::All,
+      VersionSelect::From(2),
+      VersionSelect::UpTo(2),
+      VersionSelect::Range(2, 3),
+      VersionSelect::Last(2),
+    ];
+    for select in selects {
+      let read = eventstore.read("todo1".to_string(), select).await.unwrap();
+      let streamed = eventstore
+        .stream("todo1".to_string(), select)
+        .map(|x| x.unwrap())
+        .collect::<Vec<_>>()
+        .await;
+      assert_eq!(streamed, read, "{:?}", select);
+    }
+    let read = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    let versions: Vec<_> = read.iter().map(|x| x.version).collect();
+    assert_eq!(versions, vec![3, 1, 4, 2]);
+  }
+
+  #[test]
+  fn should_start_stream_walk_when_polled() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::<TodoEvent>::new(&fixture.path);
+    let stream = eventstore.stream("todo1".to_string(), VersionSelect::All);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let events = runtime.block_on(stream.collect::<Vec<_>>());
+    assert!(events.is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_load_aggregates_created_after_snapshot() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let snapshot = InMemorySnapshot::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+    snapshot.save(root.clone()).await.unwrap();
+
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo2".to_string(),
+        title: "Eat pizza".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let ids = eventstore.aggregate_ids().await.unwrap();
+    assert_eq!(ids, vec!["todo1".to_string(), "todo2".to_string()]);
+
+    let loaded = load_aggregate(eventstore, snapshot).await.unwrap();
+    assert_eq!(loaded.get_version("todo1"), Some(&1));
+    assert_eq!(loaded.get_state("todo2").unwrap().title, "Eat pizza");
+  }
+
+  #[tokio::test]
+  async fn should_list_aggregate_ids_without_decoding_events() {
+    let fixture = FixtureRepository::setup();
+    // Its envelope is readable, but the event itself is not.
+    commit(
+      &fixture.path,
+      CommitMessage {
+        subject: "[event] TodoRemoved".to_string(),
+        body: r#"{"aggregate_id":"todo0","version":1,"event":{"name":"TodoRemoved"}}"#.to_string(),
+      },
+    )
+    .unwrap();
+    commit(&fixture.path, SNAPSHOT_MSG).unwrap();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let marker = eventstore.snapshot_marker().await.unwrap().unwrap();
+    let events = AggregateRoot::<Todo>::default()
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let ids = eventstore.aggregate_ids().await.unwrap();
+    assert_eq!(ids, vec!["todo0".to_string(), "todo1".to_string()]);
+    let ids = eventstore
+      .aggregate_ids_since_snapshot(&marker)
+      .await
+      .unwrap();
+    assert_eq!(ids, vec!["todo1".to_string()]);
+    // An empty snapshot covers nothing, whatever snapshot commits precede it.
+    let root = InMemorySnapshot::<Todo>::default().load().await.unwrap();
+    assert_eq!(root.snapshot_marker, None);
+    let ids = eventstore
+      .aggregate_ids_since_snapshot("unknown")
+      .await
+      .unwrap();
+    assert_eq!(ids, vec!["todo0".to_string(), "todo1".to_string()]);
+  }
+
+  #[tokio::test]
+  async fn should_load_aggregates_committed_before_snapshot_commit() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::new(&fixture.path);
+    let events = AggregateRoot::<Todo>::default()
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+    commit(&fixture.path, SNAPSHOT_MSG).unwrap();
+
+    let dir = FixtureRepository::setup();
+    let snapshot = FsSnapshot::<Todo>::new(&dir.path);
+    let loaded = load_aggregate(eventstore.clone(), snapshot.clone())
+      .await
+      .unwrap();
+    assert_eq!(loaded.get_state("todo1").unwrap().title, "Drink coffee");
+
+    let events = AggregateRoot::<Todo>::default()
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo2".to_string(),
+        title: "Eat pizza".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    // The saved snapshot records where it was loaded, so only later commits are listed.
+    let saved = snapshot.load().await.unwrap();
+    let marker = saved.snapshot_marker.unwrap();
+    let ids = eventstore
+      .aggregate_ids_since_snapshot(&marker)
+      .await
+      .unwrap();
+    assert_eq!(ids, vec!["todo2".to_string()]);
+    let loaded = load_aggregate(eventstore, snapshot).await.unwrap();
+    assert_eq!(loaded.get_version("todo1"), Some(&1));
+    assert_eq!(loaded.get_state("todo2").unwrap().title, "Eat pizza");
+  }
+
+  #[tokio::test]
+  async fn should_redact_shredded_fields_without_rewriting_history() {
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, Event)]
+    #[serde(tag = "name")]
+    enum UserEvent {
+      UserRegistered {
+        #[event(personal_data)]
+        email: String,
+      },
+    }
+
+    let fixture = FixtureRepository::setup();
+    let key_store = InMemoryKeyStore::default();
+    let codec = EventCodec::new().key_store(Arc::new(key_store.clone()));
+    let eventstore = GitEventstore::new(&fixture.path).with_codec(codec);
+    eventstore
+      .append(vec![PersistedEvent {
+        aggregate_id: "user1".to_string(),
+        version: 1,
+        event: UserEvent::UserRegistered {
+          email: "user1@example.com".to_string(),
+        },
+        metadata: Metadata::default(),
+      }])
+      .await
+      .unwrap();
+    let repo = Repository::open(&fixture.path).unwrap();
+    let head = get_head_commit(&repo).unwrap();
+    assert!(!head.message.body.contains("user1@example.com"));
+
+    key_store.delete_key("user1").unwrap();
+    let events = eventstore
+      .read("user1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(events[0].version, 1);
+    assert_eq!(
+      events[0].event,
+      UserEvent::UserRegistered {
+        email: REDACTED.to_string()
+      }
+    );
+  }
 }
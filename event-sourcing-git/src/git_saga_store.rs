@@ -0,0 +1,174 @@
+use std::fs::{create_dir_all, read, rename, write};
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use geeks_event_sourcing::{Saga, SagaRecord, SagaStore};
+use geeks_git::GitError;
+use git2::Repository;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::GitSagaStoreError;
+
+/// Keeps saga records as JSON inside the git directory, next to the projection checkpoints,
+/// so sagas resume where they stopped after a restart.
+pub struct GitSagaStore<G>
+where
+  G: Saga,
+{
+  repo_path: PathBuf,
+  _saga: PhantomData<fn() -> G>,
+}
+
+impl<G> GitSagaStore<G>
+where
+  G: Saga,
+{
+  pub fn new(repo_path: &Path) -> Self {
+    Self {
+      repo_path: repo_path.to_path_buf(),
+      _saga: PhantomData,
+    }
+  }
+
+  fn sagas_dir(&self) -> Result<PathBuf, GitError> {
+    let repo = Repository::open(&self.repo_path)?;
+    Ok(repo.path().join("geeks").join("sagas"))
+  }
+
+  fn file_name(name: &str) -> String {
+    format!("{}.json", URL_SAFE_NO_PAD.encode(name))
+  }
+}
+
+#[async_trait]
+impl<G> SagaStore<G> for GitSagaStore<G>
+where
+  G: Saga,
+  G::State: Serialize + DeserializeOwned,
+  G::Command: Serialize + DeserializeOwned,
+{
+  type Error = GitSagaStoreError;
+
+  async fn load(&self, name: &str) -> Result<SagaRecord<G::State, G::Command>, Self::Error> {
+    let file_path = self.sagas_dir()?.join(Self::file_name(name));
+    let raw = match read(file_path) {
+      Ok(x) => x,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(SagaRecord::default()),
+      Err(e) => return Err(e.into()),
+    };
+
+    Ok(serde_json::from_slice(&raw)?)
+  }
+
+  async fn save(
+    &self,
+    name: &str,
+    record: SagaRecord<G::State, G::Command>,
+  ) -> Result<(), Self::Error> {
+    let dir = self.sagas_dir()?;
+    create_dir_all(&dir)?;
+
+    let file_name = Self::file_name(name);
+    let tmp_path = dir.join(format!(".{}.tmp", file_name));
+    write(&tmp_path, serde_json::to_vec(&record)?)?;
+    rename(tmp_path, dir.join(file_name))?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+  use geeks_event_sourcing::testing::{InMemorySnapshot, Todo, TodoCommand, TodoEvent};
+  use geeks_event_sourcing::{
+    run_saga, AggregateRoot, CommandHandler, Dispatcher, Metadata, PositionedEvent, Saga, SagaStore,
+  };
+  use geeks_git_testing::FixtureRepository;
+
+  use crate::{GitEventstore, GitSagaStore};
+
+  struct ReminderSaga;
+
+  impl Saga for ReminderSaga {
+    type Event = TodoEvent;
+    type Command = TodoCommand;
+    type State = u32;
+
+    fn name(&self) -> &str {
+      "reminders"
+    }
+
+    fn handle(&self, state: &mut u32, event: &PositionedEvent<TodoEvent>) -> Vec<TodoCommand> {
+      match &event.persisted.event {
+        TodoEvent::TodoCreated { id, .. } if !id.starts_with("reminder_") => {
+          *state += 1;
+          vec![TodoCommand::CreateTodo {
+            id: format!("reminder_{}", id),
+            title: "Reminder".to_string(),
+            status: None,
+          }]
+        }
+        _ => Vec::new(),
+      }
+    }
+  }
+
+  struct Crash;
+
+  #[async_trait]
+  impl CommandHandler<TodoCommand> for Crash {
+    type Error = &'static str;
+
+    async fn handle(
+      &mut self,
+      _command: TodoCommand,
+      _metadata: Metadata,
+    ) -> Result<(), Self::Error> {
+      Err("crashed")
+    }
+  }
+
+  #[tokio::test]
+  async fn should_resume_saga_after_restart() {
+    let fixture = FixtureRepository::setup();
+    let eventstore = GitEventstore::<TodoEvent>::new(&fixture.path);
+    let mut dispatcher = Dispatcher::new(
+      AggregateRoot::<Todo>::default(),
+      eventstore.clone(),
+      InMemorySnapshot::default(),
+    );
+    dispatcher
+      .dispatch(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .await
+      .unwrap();
+
+    let store = GitSagaStore::<ReminderSaga>::new(&fixture.path);
+    run_saga(&ReminderSaga, &eventstore, &store, &mut Crash)
+      .await
+      .unwrap_err();
+    drop(store);
+
+    let store = GitSagaStore::<ReminderSaga>::new(&fixture.path);
+    let record = store.load("reminders").await.unwrap();
+    assert_eq!(record.state, 1);
+    assert_eq!(record.position, 1);
+    assert_eq!(record.pending.len(), 1);
+
+    let position = run_saga(&ReminderSaga, &eventstore, &store, &mut dispatcher)
+      .await
+      .unwrap();
+    assert_eq!(position, 2);
+    assert!(dispatcher.root().get_state("reminder_todo1").is_some());
+    assert!(store.load("reminders").await.unwrap().pending.is_empty());
+  }
+}
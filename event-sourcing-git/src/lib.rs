@@ -1,5 +1,11 @@
 pub use crate::commit_snapshot::*;
+pub use crate::error::*;
+pub use crate::git_checkpoint_store::*;
 pub use crate::git_eventstore::*;
+pub use crate::git_saga_store::*;
 
 mod commit_snapshot;
+mod error;
+mod git_checkpoint_store;
 mod git_eventstore;
+mod git_saga_store;
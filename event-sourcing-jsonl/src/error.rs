@@ -0,0 +1,15 @@
+use std::io;
+
+use geeks_event_sourcing::CodecError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum JsonlEventstoreError {
+  #[error("io error: {0}")]
+  IoError(#[from] io::Error),
+
+  #[error("codec error: {0}")]
+  CodecError(#[from] CodecError),
+
+  #[error("json parse error: {0}")]
+  JsonParseError(#[from] serde_json::Error),
+}
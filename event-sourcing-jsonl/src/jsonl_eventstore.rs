@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use fs2::FileExt;
+use geeks_event_sourcing::{
+  AppendError, Event, EventBus, EventCodec, EventPublisher, Eventstore, PersistedEvent, Position,
+  PositionedEvent, Version, VersionSelect,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::JsonlEventstoreError;
+
+/// Location of one line of the events file. The sidecar index holds one entry per line, in
+/// the same order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+  aggregate_id: String,
+  version: Version,
+  offset: u64,
+  len: u64,
+}
+
+impl IndexEntry {
+  fn end(&self) -> u64 {
+    self.offset + self.len
+  }
+}
+
+#[derive(Deserialize)]
+struct EventKey {
+  aggregate_id: String,
+  version: Version,
+}
+
+#[derive(Debug, Default)]
+struct JsonlIndex {
+  entries: Vec<IndexEntry>,
+  streams: HashMap<String, Vec<usize>>,
+  /// Number of entries and bytes known to be written to the sidecar.
+  persisted_entries: usize,
+  persisted_len: u64,
+}
+
+impl JsonlIndex {
+  fn end(&self) -> u64 {
+    self.entries.last().map(IndexEntry::end).unwrap_or(0)
+  }
+
+  fn push(&mut self, entry: IndexEntry) {
+    self
+      .streams
+      .entry(entry.aggregate_id.to_owned())
+      .or_default()
+      .push(self.entries.len());
+    self.entries.push(entry);
+  }
+
+  fn stream<'a>(&'a self, aggregate_id: &str) -> impl Iterator<Item = &'a IndexEntry> {
+    self
+      .streams
+      .get(aggregate_id)
+      .into_iter()
+      .flatten()
+      .map(|index| &self.entries[*index])
+  }
+
+  fn version(&self, aggregate_id: &str) -> Version {
+    self
+      .stream(aggregate_id)
+      .last()
+      .map(|x| x.version)
+      .unwrap_or(0)
+  }
+}
+
+/// Stores events as an append-only JSON Lines file, with a sidecar index of line offsets so
+/// reading one aggregate does not scan the whole file.
+///
+/// Processes sharing the file coordinate through an advisory lock on a `.lock` file next to it.
+#[derive(Clone)]
+pub struct JsonlEventstore<T>
+where
+  T: Event,
+{
+  file_path: PathBuf,
+  index: Arc<Mutex<JsonlIndex>>,
+  codec: EventCodec<T>,
+  event_bus: EventBus<T>,
+}
+
+impl<T> JsonlEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  /// Opens or creates the events file, recovering from a crash in the middle of an append.
+  pub fn open(file_path: &Path) -> Result<Self, JsonlEventstoreError> {
+    let this = Self {
+      file_path: file_path.to_path_buf(),
+      index: Arc::default(),
+      codec: EventCodec::default(),
+      event_bus: EventBus::default(),
+    };
+
+    {
+      let mut index = this.lock_index();
+      let _lock = this.lock_exclusive()?;
+      this.truncate_torn_line()?;
+      this.load_index(&mut index)?;
+      this.refresh(&mut index)?;
+      this.persist_index(&mut index)?;
+    }
+
+    Ok(this)
+  }
+
+  #[must_use]
+  pub fn with_codec(self, codec: EventCodec<T>) -> Self {
+    Self { codec, ..self }
+  }
+
+  fn sibling(&self, suffix: &str) -> PathBuf {
+    let mut file_name = self.file_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(suffix);
+    self.file_path.with_file_name(file_name)
+  }
+
+  fn index_path(&self) -> PathBuf {
+    self.sibling(".idx")
+  }
+
+  fn lock_index(&self) -> MutexGuard<'_, JsonlIndex> {
+    self.index.lock().expect("locked")
+  }
+
+  /// The lock is released when the returned file is dropped.
+  fn lock_file(&self, exclusive: bool) -> io::Result<File> {
+    let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(self.sibling(".lock"))?;
+    if exclusive {
+      FileExt::lock_exclusive(&file)?;
+    } else {
+      FileExt::lock_shared(&file)?;
+    }
+
+    Ok(file)
+  }
+
+  fn lock_shared(&self) -> io::Result<File> {
+    self.lock_file(false)
+  }
+
+  fn lock_exclusive(&self) -> io::Result<File> {
+    self.lock_file(true)
+  }
+
+  /// Drops a final line without a newline, which a writer left behind when it crashed.
+  fn truncate_torn_line(&self) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .append(true)
+      .open(&self.file_path)?;
+    let len = file.metadata()?.len();
+    let complete = complete_len(&mut file, len)?;
+    if complete < len {
+      file.set_len(complete)?;
+      file.sync_all()?;
+    }
+
+    Ok(())
+  }
+
+  /// Loads entries of the sidecar which still match the events file. Anything after the first
+  /// mismatch is cut off and rebuilt by `refresh`.
+  fn load_index(&self, index: &mut JsonlIndex) -> Result<(), JsonlEventstoreError> {
+    let raw = match read_to_string(self.index_path()) {
+      Ok(x) => x,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+      Err(e) => return Err(e.into()),
+    };
+
+    let events_len = File::open(&self.file_path)?.metadata()?.len();
+    for line in raw.split_inclusive('\n') {
+      let entry: IndexEntry = match serde_json::from_str(line) {
+        Ok(x) if line.ends_with('\n') => x,
+        _ => break,
+      };
+      if entry.offset != index.end() || entry.end() > events_len {
+        break;
+      }
+      index.push(entry);
+      index.persisted_entries += 1;
+      index.persisted_len += line.len() as u64;
+    }
+
+    if index.persisted_len < raw.len() as u64 {
+      let sidecar = OpenOptions::new().write(true).open(self.index_path())?;
+      sidecar.set_len(index.persisted_len)?;
+    }
+
+    Ok(())
+  }
+
+  /// Indexes lines appended since the index was last updated, possibly by other processes.
+  fn refresh(&self, index: &mut JsonlIndex) -> Result<(), JsonlEventstoreError> {
+    let mut file = File::open(&self.file_path)?;
+    let mut offset = index.end();
+    if file.metadata()?.len() <= offset {
+      return Ok(());
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    loop {
+      line.clear();
+      let len = reader.read_line(&mut line)? as u64;
+      // Torn lines are only dropped under the exclusive lock, so they are skipped meanwhile.
+      if len == 0 || !line.ends_with('\n') {
+        break;
+      }
+
+      let key: EventKey = serde_json::from_str(&line)?;
+      index.push(IndexEntry {
+        aggregate_id: key.aggregate_id,
+        version: key.version,
+        offset,
+        len,
+      });
+      offset += len;
+    }
+
+    Ok(())
+  }
+
+  /// Writes entries missing from the sidecar. Must be called under the exclusive lock. Other
+  /// processes may have written some of them already, which shows in the sidecar length.
+  fn persist_index(&self, index: &mut JsonlIndex) -> Result<(), JsonlEventstoreError> {
+    let mut sidecar = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .append(true)
+      .open(self.index_path())?;
+    let len = sidecar.metadata()?.len();
+    if len > index.persisted_len {
+      let mut tail = String::new();
+      sidecar.seek(SeekFrom::Start(index.persisted_len))?;
+      sidecar.read_to_string(&mut tail)?;
+      for line in tail.split_inclusive('\n').filter(|x| x.ends_with('\n')) {
+        index.persisted_entries += 1;
+        index.persisted_len += line.len() as u64;
+      }
+      if index.persisted_len < len {
+        sidecar.set_len(index.persisted_len)?;
+      }
+    }
+
+    let mut raw = String::new();
+    for entry in index.entries.iter().skip(index.persisted_entries) {
+      raw.push_str(&serde_json::to_string(entry)?);
+      raw.push('\n');
+    }
+    sidecar.write_all(raw.as_bytes())?;
+    index.persisted_entries = index.entries.len();
+    index.persisted_len += raw.len() as u64;
+
+    Ok(())
+  }
+
+  fn read_entries(
+    &self,
+    entries: &[IndexEntry],
+  ) -> Result<Vec<PersistedEvent<T>>, JsonlEventstoreError> {
+    let mut file = File::open(&self.file_path)?;
+    entries
+      .iter()
+      .map(|entry| {
+        let mut raw = vec![0; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut raw)?;
+        let raw =
+          std::str::from_utf8(&raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(self.codec.decode(raw.trim_end())?)
+      })
+      .collect()
+  }
+
+  /// Appends `events` and syncs them to disk. Must be called under the exclusive lock.
+  fn write_events(
+    &self,
+    index: &mut JsonlIndex,
+    events: Vec<PersistedEvent<T>>,
+  ) -> Result<Vec<PositionedEvent<T>>, JsonlEventstoreError> {
+    let mut raw = String::new();
+    let mut lens = Vec::with_capacity(events.len());
+    for persisted in &events {
+      let line = self.codec.encode(persisted)?;
+      raw.push_str(&line);
+      raw.push('\n');
+      lens.push(line.len() as u64 + 1);
+    }
+
+    let mut file = OpenOptions::new().append(true).open(&self.file_path)?;
+    file.write_all(raw.as_bytes())?;
+    file.sync_data()?;
+
+    let mut appended = Vec::with_capacity(events.len());
+    for (persisted, len) in events.into_iter().zip(lens) {
+      index.push(IndexEntry {
+        aggregate_id: persisted.aggregate_id.to_owned(),
+        version: persisted.version,
+        offset: index.end(),
+        len,
+      });
+      appended.push(PositionedEvent {
+        position: index.entries.len() as Position,
+        persisted,
+      });
+    }
+    self.persist_index(index)?;
+
+    Ok(appended)
+  }
+}
+
+/// Length of `file` up to and including its last newline.
+fn complete_len(file: &mut File, len: u64) -> io::Result<u64> {
+  let mut buf = [0; 4096];
+  let mut end = len;
+  while end > 0 {
+    let start = end.saturating_sub(buf.len() as u64);
+    let chunk = &mut buf[..(end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(chunk)?;
+    if let Some(i) = chunk.iter().rposition(|x| *x == b'\n') {
+      return Ok(start + i as u64 + 1);
+    }
+    end = start;
+  }
+
+  Ok(0)
+}
+
+#[async_trait]
+impl<T> Eventstore for JsonlEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  type Event = T;
+  type Error = JsonlEventstoreError;
+
+  async fn read(
+    &self,
+    aggregate_id: String,
+    select: VersionSelect,
+  ) -> Result<Vec<PersistedEvent<Self::Event>>, Self::Error> {
+    let mut index = self.lock_index();
+    let _lock = self.lock_shared()?;
+    self.refresh(&mut index)?;
+
+    let mut entries: Vec<_> = index
+      .stream(&aggregate_id)
+      .filter(|x| select.contains(x.version))
+      .cloned()
+      .collect();
+    if let VersionSelect::Last(n) = select {
+      entries.drain(..entries.len().saturating_sub(n as usize));
+    }
+
+    self.read_entries(&entries)
+  }
+
+  async fn append(&self, events: Vec<PersistedEvent<Self::Event>>) -> Result<(), Self::Error> {
+    let mut index = self.lock_index();
+    let lock = self.lock_exclusive()?;
+    self.truncate_torn_line()?;
+    self.refresh(&mut index)?;
+    let appended = self.write_events(&mut index, events)?;
+    drop(lock);
+    drop(index);
+    self.event_bus.publish(appended);
+
+    Ok(())
+  }
+
+  async fn append_expected(
+    &self,
+    aggregate_id: String,
+    expected: Version,
+    events: Vec<PersistedEvent<Self::Event>>,
+  ) -> Result<(), AppendError<Self::Error>> {
+    let fail = |e: JsonlEventstoreError| AppendError::EventstoreError(e);
+    AppendError::check_events(&aggregate_id, expected, &events)?;
+
+    let mut index = self.lock_index();
+    let lock = self.lock_exclusive().map_err(|e| fail(e.into()))?;
+    self.truncate_torn_line().map_err(|e| fail(e.into()))?;
+    self.refresh(&mut index).map_err(fail)?;
+
+    let actual = index.version(&aggregate_id);
+    if actual != expected {
+      return Err(AppendError::VersionConflict {
+        aggregate_id,
+        expected,
+        actual,
+      });
+    }
+    let appended = self.write_events(&mut index, events).map_err(fail)?;
+    drop(lock);
+    drop(index);
+    self.event_bus.publish(appended);
+
+    Ok(())
+  }
+
+  async fn read_all(
+    &self,
+    after: Position,
+  ) -> Result<Vec<PositionedEvent<Self::Event>>, Self::Error> {
+    let mut index = self.lock_index();
+    let _lock = self.lock_shared()?;
+    self.refresh(&mut index)?;
+
+    let entries = index.entries.get(after as usize..).unwrap_or_default();
+    let events = self
+      .read_entries(entries)?
+      .into_iter()
+      .enumerate()
+      .map(|(i, persisted)| PositionedEvent {
+        position: after + i as Position + 1,
+        persisted,
+      })
+      .collect();
+
+    Ok(events)
+  }
+
+  async fn aggregate_ids(&self) -> Result<Vec<String>, Self::Error> {
+    let mut index = self.lock_index();
+    let _lock = self.lock_shared()?;
+    self.refresh(&mut index)?;
+
+    let mut ids: Vec<_> = index.streams.iter().collect();
+    ids.sort_by_key(|(_, indexes)| indexes[0]);
+
+    Ok(ids.into_iter().map(|(id, _)| id.to_owned()).collect())
+  }
+}
+
+impl<T> EventPublisher for JsonlEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  fn event_bus(&self) -> &EventBus<T> {
+    &self.event_bus
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+  use std::fs::{read_to_string, OpenOptions};
+  use std::io::Write;
+
+  use geeks_git_testing::FixtureRepository;
+
+  use geeks_event_sourcing::testing::{run_eventstore_suite, title_updated, TodoEvent};
+  use geeks_event_sourcing::{AppendError, Eventstore, VersionSelect};
+
+  use crate::JsonlEventstore;
+
+  #[tokio::test]
+  async fn should_behave_like_an_eventstore() {
+    let fixture = FixtureRepository::setup();
+    let files = Cell::new(0);
+    run_eventstore_suite(|| {
+      files.set(files.get() + 1);
+      let file_path = fixture.path.join(format!("events{}.jsonl", files.get()));
+      JsonlEventstore::<TodoEvent>::open(&file_path).unwrap()
+    })
+    .await;
+  }
+
+  #[tokio::test]
+  async fn should_append_and_read_after_reopen() {
+    let fixture = FixtureRepository::setup();
+    let file_path = fixture.path.join("events.jsonl");
+    let eventstore = JsonlEventstore::open(&file_path).unwrap();
+    eventstore
+      .append_expected("todo1".to_string(), 0, vec![title_updated("todo1", 1)])
+      .await
+      .unwrap();
+    eventstore
+      .append(vec![title_updated("todo2", 1), title_updated("todo1", 2)])
+      .await
+      .unwrap();
+
+    let err = eventstore
+      .append_expected("todo1".to_string(), 1, vec![title_updated("todo1", 2)])
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      AppendError::VersionConflict {
+        expected: 1,
+        actual: 2,
+        ..
+      }
+    ));
+
+    let reopened = JsonlEventstore::<TodoEvent>::open(&file_path).unwrap();
+    let events = reopened
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(
+      events,
+      vec![title_updated("todo1", 1), title_updated("todo1", 2)]
+    );
+    let events = reopened
+      .read("todo1".to_string(), VersionSelect::Last(1))
+      .await
+      .unwrap();
+    assert_eq!(events, vec![title_updated("todo1", 2)]);
+
+    let positions: Vec<_> = reopened
+      .read_all(1)
+      .await
+      .unwrap()
+      .into_iter()
+      .map(|x| (x.position, x.persisted.aggregate_id))
+      .collect();
+    assert_eq!(
+      positions,
+      vec![(2, "todo2".to_string()), (3, "todo1".to_string())]
+    );
+    assert_eq!(
+      reopened.aggregate_ids().await.unwrap(),
+      vec!["todo1".to_string(), "todo2".to_string()]
+    );
+  }
+
+  #[tokio::test]
+  async fn should_truncate_torn_line_on_open() {
+    let fixture = FixtureRepository::setup();
+    let file_path = fixture.path.join("events.jsonl");
+    let eventstore = JsonlEventstore::open(&file_path).unwrap();
+    eventstore
+      .append(vec![title_updated("todo1", 1)])
+      .await
+      .unwrap();
+    let complete = read_to_string(&file_path).unwrap();
+
+    let mut file = OpenOptions::new().append(true).open(&file_path).unwrap();
+    file.write_all(br#"{"aggregate_id":"todo1","vers"#).unwrap();
+    drop(file);
+
+    let eventstore = JsonlEventstore::<TodoEvent>::open(&file_path).unwrap();
+    assert_eq!(read_to_string(&file_path).unwrap(), complete);
+    eventstore
+      .append_expected("todo1".to_string(), 1, vec![title_updated("todo1", 2)])
+      .await
+      .unwrap();
+    let versions: Vec<_> = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap()
+      .into_iter()
+      .map(|x| x.version)
+      .collect();
+    assert_eq!(versions, vec![1, 2]);
+  }
+
+  #[tokio::test]
+  async fn should_rebuild_stale_sidecar_index() {
+    let fixture = FixtureRepository::setup();
+    let file_path = fixture.path.join("events.jsonl");
+    let index_path = fixture.path.join("events.jsonl.idx");
+    let eventstore = JsonlEventstore::open(&file_path).unwrap();
+    eventstore
+      .append((1..=3).map(|x| title_updated("todo1", x)).collect())
+      .await
+      .unwrap();
+
+    let index = read_to_string(&index_path).unwrap();
+    assert_eq!(index.lines().count(), 3);
+    let first_line = index.split_inclusive('\n').next().unwrap();
+    std::fs::write(&index_path, format!("{}{{\"aggregate_id\"", first_line)).unwrap();
+
+    let eventstore = JsonlEventstore::<TodoEvent>::open(&file_path).unwrap();
+    assert_eq!(read_to_string(&index_path).unwrap(), index);
+    let events = eventstore
+      .read("todo1".to_string(), VersionSelect::From(2))
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn should_see_events_appended_by_other_instances() {
+    let fixture = FixtureRepository::setup();
+    let file_path = fixture.path.join("events.jsonl");
+    let first = JsonlEventstore::open(&file_path).unwrap();
+    let second = JsonlEventstore::open(&file_path).unwrap();
+
+    first.append(vec![title_updated("todo1", 1)]).await.unwrap();
+    second
+      .append_expected("todo1".to_string(), 1, vec![title_updated("todo1", 2)])
+      .await
+      .unwrap();
+    first
+      .append_expected("todo1".to_string(), 2, vec![title_updated("todo1", 3)])
+      .await
+      .unwrap();
+
+    let index = read_to_string(fixture.path.join("events.jsonl.idx")).unwrap();
+    assert_eq!(index.lines().count(), 3);
+    let events = second
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 3);
+  }
+}
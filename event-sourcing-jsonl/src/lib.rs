@@ -0,0 +1,5 @@
+pub use crate::error::*;
+pub use crate::jsonl_eventstore::*;
+
+mod error;
+mod jsonl_eventstore;
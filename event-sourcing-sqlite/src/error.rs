@@ -0,0 +1,22 @@
+use geeks_event_sourcing::CodecError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqliteEventstoreError {
+  #[error("sqlite error: {0}")]
+  SqliteError(#[from] rusqlite::Error),
+
+  #[error("codec error: {0}")]
+  CodecError(#[from] CodecError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqliteSnapshotError {
+  #[error("sqlite error: {0}")]
+  SqliteError(#[from] rusqlite::Error),
+
+  #[error("json parse error: {0}")]
+  JsonParseError(#[from] serde_json::Error),
+
+  #[error("snapshot version mismatch: stored {stored}, expected {expected}")]
+  VersionMismatch { stored: u32, expected: u32 },
+}
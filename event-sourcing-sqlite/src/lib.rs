@@ -0,0 +1,7 @@
+pub use crate::error::*;
+pub use crate::sqlite_eventstore::*;
+pub use crate::sqlite_snapshot::*;
+
+mod error;
+mod sqlite_eventstore;
+mod sqlite_snapshot;
@@ -0,0 +1,420 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use geeks_event_sourcing::{
+  AppendError, Event, EventBus, EventCodec, EventPublisher, Eventstore, PersistedEvent, Position,
+  PositionedEvent, Timestamp, Version, VersionSelect,
+};
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::SqliteEventstoreError;
+
+/// Number of events read per query while streaming.
+const STREAM_PAGE_SIZE: u64 = 256;
+
+/// SQLite integers are signed, so this stands for "no upper bound".
+const MAX_VERSION: Version = i64::MAX as Version;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+  position INTEGER PRIMARY KEY AUTOINCREMENT,
+  aggregate_id TEXT NOT NULL,
+  version INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  recorded_at INTEGER,
+  body TEXT NOT NULL,
+  UNIQUE (aggregate_id, version)
+);
+";
+
+#[derive(Clone)]
+pub struct SqliteEventstore<T>
+where
+  T: Event,
+{
+  connection: Arc<Mutex<Connection>>,
+  codec: EventCodec<T>,
+  event_bus: EventBus<T>,
+}
+
+impl<T> SqliteEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  pub fn open(path: &Path) -> Result<Self, SqliteEventstoreError> {
+    Self::with_connection(Arc::new(Mutex::new(Connection::open(path)?)))
+  }
+
+  pub fn open_in_memory() -> Result<Self, SqliteEventstoreError> {
+    Self::with_connection(Arc::new(Mutex::new(Connection::open_in_memory()?)))
+  }
+
+  /// Uses a connection which can be shared with a `SqliteSnapshot`.
+  pub fn with_connection(
+    connection: Arc<Mutex<Connection>>,
+  ) -> Result<Self, SqliteEventstoreError> {
+    connection.lock().expect("locked").execute_batch(SCHEMA)?;
+
+    Ok(Self {
+      connection,
+      codec: EventCodec::default(),
+      event_bus: EventBus::default(),
+    })
+  }
+
+  #[must_use]
+  pub fn with_codec(self, codec: EventCodec<T>) -> Self {
+    Self { codec, ..self }
+  }
+
+  pub fn connection(&self) -> Arc<Mutex<Connection>> {
+    Arc::clone(&self.connection)
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Connection> {
+    self.connection.lock().expect("locked")
+  }
+
+  fn read_page(
+    &self,
+    aggregate_id: &str,
+    from: Version,
+    to: Version,
+    limit: u64,
+  ) -> Result<Vec<PersistedEvent<T>>, SqliteEventstoreError> {
+    let connection = self.lock();
+    let mut statement = connection.prepare_cached(
+      "SELECT body FROM events
+       WHERE aggregate_id = ?1 AND version BETWEEN ?2 AND ?3
+       ORDER BY version LIMIT ?4",
+    )?;
+    let bodies = statement.query_map(params![aggregate_id, from, to, limit], |row| row.get(0))?;
+
+    bodies.map(|body| self.decode(body?)).collect()
+  }
+
+  fn read_last(
+    &self,
+    aggregate_id: &str,
+    n: u64,
+  ) -> Result<Vec<PersistedEvent<T>>, SqliteEventstoreError> {
+    let connection = self.lock();
+    let mut statement = connection.prepare_cached(
+      "SELECT body FROM events WHERE aggregate_id = ?1 ORDER BY version DESC LIMIT ?2",
+    )?;
+    let bodies = statement.query_map(params![aggregate_id, n], |row| row.get(0))?;
+    let mut events = bodies
+      .map(|body| self.decode(body?))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    events.reverse();
+    Ok(events)
+  }
+
+  fn read_selected(
+    &self,
+    aggregate_id: &str,
+    select: VersionSelect,
+  ) -> Result<Vec<PersistedEvent<T>>, SqliteEventstoreError> {
+    match select {
+      VersionSelect::Last(n) => self.read_last(aggregate_id, n),
+      _ => self.read_page(
+        aggregate_id,
+        select.lower_bound().unwrap_or(0),
+        select.upper_bound().unwrap_or(MAX_VERSION),
+        MAX_VERSION,
+      ),
+    }
+  }
+
+  fn decode(&self, body: String) -> Result<PersistedEvent<T>, SqliteEventstoreError> {
+    Ok(self.codec.decode(&body)?)
+  }
+
+  fn insert(
+    &self,
+    transaction: &Transaction,
+    persisted: &PersistedEvent<T>,
+  ) -> Result<Position, SqliteEventstoreError> {
+    transaction.execute(
+      "INSERT INTO events (aggregate_id, version, name, recorded_at, body)
+       VALUES (?1, ?2, ?3, ?4, ?5)",
+      params![
+        persisted.aggregate_id,
+        persisted.version,
+        persisted.event.name(),
+        persisted.metadata.recorded_at,
+        self.codec.encode(persisted)?,
+      ],
+    )?;
+
+    Ok(transaction.last_insert_rowid() as Position)
+  }
+
+  fn insert_all(
+    &self,
+    transaction: &Transaction,
+    events: Vec<PersistedEvent<T>>,
+  ) -> Result<Vec<PositionedEvent<T>>, SqliteEventstoreError> {
+    events
+      .into_iter()
+      .map(|persisted| {
+        let position = self.insert(transaction, &persisted)?;
+        Ok(PositionedEvent {
+          position,
+          persisted,
+        })
+      })
+      .collect()
+  }
+}
+
+#[async_trait]
+impl<T> Eventstore for SqliteEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  type Event = T;
+  type Error = SqliteEventstoreError;
+
+  async fn read(
+    &self,
+    aggregate_id: String,
+    select: VersionSelect,
+  ) -> Result<Vec<PersistedEvent<Self::Event>>, Self::Error> {
+    self.read_selected(&aggregate_id, select)
+  }
+
+  fn stream(
+    &self,
+    aggregate_id: String,
+    select: VersionSelect,
+  ) -> BoxStream<'_, Result<PersistedEvent<Self::Event>, Self::Error>> {
+    let (from, to) = match select {
+      // The tail is bounded, so it is read at once.
+      VersionSelect::Last(_) => {
+        return match self.read_selected(&aggregate_id, select) {
+          Ok(events) => stream::iter(events.into_iter().map(Ok)).boxed(),
+          Err(e) => stream::once(async { Err(e) }).boxed(),
+        };
+      }
+      _ => (
+        select.lower_bound().unwrap_or(0),
+        select.upper_bound().unwrap_or(MAX_VERSION),
+      ),
+    };
+
+    stream::unfold(Some(from), move |from| {
+      let aggregate_id = aggregate_id.clone();
+      async move {
+        let page = self.read_page(&aggregate_id, from?, to, STREAM_PAGE_SIZE);
+        let next = match &page {
+          Ok(events) if events.len() as u64 == STREAM_PAGE_SIZE => {
+            events.last().map(|x| x.version + 1)
+          }
+          _ => None,
+        };
+        Some((page, next))
+      }
+    })
+    .flat_map(|page| match page {
+      Ok(events) => stream::iter(events.into_iter().map(Ok)).boxed(),
+      Err(e) => stream::once(async { Err(e) }).boxed(),
+    })
+    .boxed()
+  }
+
+  async fn append(&self, events: Vec<PersistedEvent<Self::Event>>) -> Result<(), Self::Error> {
+    let mut connection = self.lock();
+    let transaction = connection.transaction()?;
+    let appended = self.insert_all(&transaction, events)?;
+    transaction.commit()?;
+    drop(connection);
+    self.event_bus.publish(appended);
+
+    Ok(())
+  }
+
+  async fn append_expected(
+    &self,
+    aggregate_id: String,
+    expected: Version,
+    events: Vec<PersistedEvent<Self::Event>>,
+  ) -> Result<(), AppendError<Self::Error>> {
+    let fail = |e: SqliteEventstoreError| AppendError::EventstoreError(e);
+    AppendError::check_events(&aggregate_id, expected, &events)?;
+
+    let mut connection = self.lock();
+    // Takes the write lock up front, so no other connection appends between the version check
+    // and the inserts.
+    let transaction = connection
+      .transaction_with_behavior(TransactionBehavior::Immediate)
+      .map_err(|e| fail(e.into()))?;
+    let actual: Version = transaction
+      .query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM events WHERE aggregate_id = ?1",
+        params![aggregate_id],
+        |row| row.get(0),
+      )
+      .map_err(|e| fail(e.into()))?;
+    if actual != expected {
+      return Err(AppendError::VersionConflict {
+        aggregate_id,
+        expected,
+        actual,
+      });
+    }
+
+    let appended = self.insert_all(&transaction, events).map_err(fail)?;
+    transaction.commit().map_err(|e| fail(e.into()))?;
+    drop(connection);
+    self.event_bus.publish(appended);
+
+    Ok(())
+  }
+
+  async fn read_all(
+    &self,
+    after: Position,
+  ) -> Result<Vec<PositionedEvent<Self::Event>>, Self::Error> {
+    let connection = self.lock();
+    let mut statement = connection
+      .prepare_cached("SELECT position, body FROM events WHERE position > ?1 ORDER BY position")?;
+    let rows = statement.query_map(params![after], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    rows
+      .map(|row| {
+        let (position, body) = row?;
+        Ok(PositionedEvent {
+          position,
+          persisted: self.decode(body)?,
+        })
+      })
+      .collect()
+  }
+
+  async fn aggregate_ids(&self) -> Result<Vec<String>, Self::Error> {
+    let connection = self.lock();
+    let mut statement = connection.prepare_cached(
+      "SELECT aggregate_id FROM events GROUP BY aggregate_id ORDER BY MIN(position)",
+    )?;
+    let ids = statement.query_map([], |row| row.get(0))?;
+
+    Ok(ids.collect::<Result<_, _>>()?)
+  }
+
+  async fn version_at(&self, aggregate_id: String, at: Timestamp) -> Result<Version, Self::Error> {
+    let version = self.lock().query_row(
+      "SELECT COALESCE(MAX(version), 0) FROM events
+       WHERE aggregate_id = ?1 AND (recorded_at IS NULL OR recorded_at <= ?2)",
+      params![aggregate_id, at],
+      |row| row.get(0),
+    )?;
+
+    Ok(version)
+  }
+
+  async fn position_at(&self, at: Timestamp) -> Result<Position, Self::Error> {
+    let position = self.lock().query_row(
+      "SELECT COALESCE(MAX(position), 0) FROM events
+       WHERE recorded_at IS NULL OR recorded_at <= ?1",
+      params![at],
+      |row| row.get(0),
+    )?;
+
+    Ok(position)
+  }
+}
+
+impl<T> EventPublisher for SqliteEventstore<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  fn event_bus(&self) -> &EventBus<T> {
+    &self.event_bus
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::StreamExt;
+  use geeks_event_sourcing::testing::{run_eventstore_suite, title_updated, TodoEvent};
+  use geeks_event_sourcing::{EventPublisher, Eventstore, VersionSelect};
+
+  use crate::{SqliteEventstore, SqliteEventstoreError};
+
+  fn eventstore() -> SqliteEventstore<TodoEvent> {
+    SqliteEventstore::open_in_memory().unwrap()
+  }
+
+  #[tokio::test]
+  async fn should_behave_like_an_eventstore() {
+    run_eventstore_suite(eventstore).await;
+  }
+
+  #[tokio::test]
+  async fn should_reject_duplicated_versions() {
+    let eventstore = eventstore();
+    eventstore
+      .append(vec![title_updated("todo1", 1)])
+      .await
+      .unwrap();
+
+    let err = eventstore
+      .append(vec![title_updated("todo1", 2), title_updated("todo1", 1)])
+      .await
+      .unwrap_err();
+    assert!(matches!(err, SqliteEventstoreError::SqliteError(_)));
+    // The whole batch is rolled back.
+    let events = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(events, vec![title_updated("todo1", 1)]);
+  }
+
+  #[tokio::test]
+  async fn should_stream_events_across_pages() {
+    let eventstore = eventstore();
+    eventstore
+      .append((1..=600).map(|x| title_updated("todo1", x)).collect())
+      .await
+      .unwrap();
+
+    let versions: Vec<_> = eventstore
+      .stream("todo1".to_string(), VersionSelect::From(10))
+      .map(|x| x.unwrap().version)
+      .collect()
+      .await;
+    assert_eq!(versions, (10..=600).collect::<Vec<_>>());
+
+    let versions: Vec<_> = eventstore
+      .stream("todo1".to_string(), VersionSelect::Last(3))
+      .map(|x| x.unwrap().version)
+      .collect()
+      .await;
+    assert_eq!(versions, vec![598, 599, 600]);
+  }
+
+  #[tokio::test]
+  async fn should_publish_appended_events_with_positions() {
+    let eventstore = eventstore();
+    eventstore
+      .append(vec![title_updated("todo1", 1)])
+      .await
+      .unwrap();
+    let mut receiver = eventstore.event_bus().receiver();
+
+    eventstore
+      .append_expected("todo1".to_string(), 1, vec![title_updated("todo1", 2)])
+      .await
+      .unwrap();
+    let published = receiver.recv().await.unwrap();
+    assert_eq!(published.position, 2);
+    assert_eq!(published.persisted, title_updated("todo1", 2));
+  }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use geeks_event_sourcing::{Aggregate, AggregateRoot, Snapshot, SnapshotInvalidation, Version};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::SqliteSnapshotError;
+
+const DEFAULT_NAME: &str = "snapshot";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS snapshots (
+  name TEXT PRIMARY KEY,
+  snapshot_version INTEGER NOT NULL,
+  states TEXT NOT NULL,
+  versions TEXT NOT NULL
+);
+";
+
+/// Stores the whole aggregate root as a single row, replaced atomically on save. A missing row
+/// loads as an empty root.
+#[derive(Debug, Clone)]
+pub struct SqliteSnapshot<T>
+where
+  T: Aggregate,
+{
+  connection: Arc<Mutex<Connection>>,
+  name: String,
+  _aggregate: PhantomData<T>,
+}
+
+impl<T> SqliteSnapshot<T>
+where
+  T: Aggregate,
+{
+  pub fn open(path: &Path) -> Result<Self, SqliteSnapshotError> {
+    Self::with_connection(Arc::new(Mutex::new(Connection::open(path)?)))
+  }
+
+  pub fn open_in_memory() -> Result<Self, SqliteSnapshotError> {
+    Self::with_connection(Arc::new(Mutex::new(Connection::open_in_memory()?)))
+  }
+
+  pub fn with_connection(connection: Arc<Mutex<Connection>>) -> Result<Self, SqliteSnapshotError> {
+    connection.lock().expect("locked").execute_batch(SCHEMA)?;
+
+    Ok(Self {
+      connection,
+      name: DEFAULT_NAME.to_string(),
+      _aggregate: PhantomData,
+    })
+  }
+
+  /// Keeps snapshots of several aggregate types apart in the same database.
+  #[must_use]
+  pub fn with_name(self, name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      ..self
+    }
+  }
+}
+
+#[async_trait]
+impl<T> Snapshot<T> for SqliteSnapshot<T>
+where
+  T: Aggregate + Serialize + DeserializeOwned,
+{
+  type Error = SqliteSnapshotError;
+
+  async fn load(&self) -> Result<AggregateRoot<T>, Self::Error> {
+    let row: Option<(u32, String, String)> = self
+      .connection
+      .lock()
+      .expect("locked")
+      .query_row(
+        "SELECT snapshot_version, states, versions FROM snapshots WHERE name = ?1",
+        params![self.name],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+      )
+      .optional()?;
+    let (snapshot_version, states, versions) = match row {
+      Some(x) => x,
+      None => return Ok(AggregateRoot::default()),
+    };
+
+    if snapshot_version != T::SNAPSHOT_VERSION {
+      return Err(SqliteSnapshotError::VersionMismatch {
+        stored: snapshot_version,
+        expected: T::SNAPSHOT_VERSION,
+      });
+    }
+    let states: HashMap<String, T> = serde_json::from_str(&states)?;
+    let versions: HashMap<String, Version> = serde_json::from_str(&versions)?;
+
+    Ok(AggregateRoot::new(states, versions))
+  }
+
+  async fn save(&self, root: AggregateRoot<T>) -> Result<(), Self::Error> {
+    let states = serde_json::to_string(&root.states)?;
+    let versions = serde_json::to_string(&root.versions)?;
+    self.connection.lock().expect("locked").execute(
+      "INSERT OR REPLACE INTO snapshots (name, snapshot_version, states, versions)
+       VALUES (?1, ?2, ?3, ?4)",
+      params![self.name, T::SNAPSHOT_VERSION, states, versions],
+    )?;
+
+    Ok(())
+  }
+
+  fn invalidation(&self, error: &Self::Error) -> Option<SnapshotInvalidation> {
+    match error {
+      SqliteSnapshotError::VersionMismatch { stored, expected } => {
+        Some(SnapshotInvalidation::VersionMismatch {
+          stored: *stored,
+          expected: *expected,
+        })
+      }
+      SqliteSnapshotError::JsonParseError(_) => {
+        Some(SnapshotInvalidation::Unreadable(error.to_string()))
+      }
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use geeks_event_sourcing::testing::{create_todo, Todo, TodoEvent};
+  use geeks_event_sourcing::{
+    load_aggregate_with_report, AggregateRoot, Eventstore, Snapshot, SnapshotInvalidation,
+  };
+
+  use crate::{SqliteEventstore, SqliteSnapshot};
+
+  #[tokio::test]
+  async fn should_save_and_load_snapshot() {
+    let snapshot = SqliteSnapshot::<Todo>::open_in_memory().unwrap();
+    assert!(snapshot.load().await.unwrap().versions.is_empty());
+
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    root.execute_command(create_todo("todo1")).unwrap();
+    snapshot.save(root).await.unwrap();
+
+    let loaded = snapshot.load().await.unwrap();
+    assert_eq!(loaded.get_version("todo1"), Some(&1));
+    assert_eq!(loaded.get_state("todo1").unwrap().title, "Drink coffee");
+
+    let other = snapshot.clone().with_name("other");
+    assert!(other.load().await.unwrap().versions.is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_rebuild_stale_snapshot_from_shared_connection() {
+    let eventstore = SqliteEventstore::<TodoEvent>::open_in_memory().unwrap();
+    let snapshot = SqliteSnapshot::<Todo>::with_connection(eventstore.connection()).unwrap();
+
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    let events = root.execute_command(create_todo("todo1")).unwrap();
+    eventstore.append(events).await.unwrap();
+    snapshot.save(root).await.unwrap();
+    eventstore
+      .connection()
+      .lock()
+      .unwrap()
+      .execute("UPDATE snapshots SET snapshot_version = 0", [])
+      .unwrap();
+
+    let (loaded, report) = load_aggregate_with_report(eventstore, snapshot.clone())
+      .await
+      .unwrap();
+    assert_eq!(
+      report.invalidated,
+      Some(SnapshotInvalidation::VersionMismatch {
+        stored: 0,
+        expected: 1
+      })
+    );
+    assert_eq!(loaded.get_version("todo1"), Some(&1));
+    assert_eq!(
+      snapshot.load().await.unwrap().get_version("todo1"),
+      Some(&1)
+    );
+  }
+}
@@ -1,8 +1,14 @@
 use std::collections::HashMap;
 
-use crate::{Command, Event, PersistedEvent, Version};
+use chrono::Utc;
+
+use crate::{Command, Event, Metadata, PersistedEvent, Version};
 
 pub trait Aggregate: Sized + Send + Sync + Clone {
+  /// Bumped whenever the state changes shape, so stored snapshots of older versions are
+  /// discarded and rebuilt from events.
+  const SNAPSHOT_VERSION: u32 = 1;
+
   type Command: Command;
   type Event: Event;
   type Error: Send + Sync;
@@ -12,7 +18,7 @@ pub trait Aggregate: Sized + Send + Sync + Clone {
   fn handle_command(
     this: Option<&Self>,
     command: Self::Command,
-  ) -> Result<Self::Event, Self::Error>;
+  ) -> Result<Vec<Self::Event>, Self::Error>;
 
   fn apply_event(this: Option<Self>, event: Self::Event) -> Result<Self, Self::Error>;
 }
@@ -24,6 +30,9 @@ where
 {
   pub states: HashMap<String, T>,
   pub versions: HashMap<String, Version>,
+  /// `Eventstore::snapshot_marker` at the time the root was loaded. Every event before it is
+  /// already applied.
+  pub snapshot_marker: Option<String>,
 }
 
 impl<T> Default for AggregateRoot<T>
@@ -34,6 +43,7 @@ where
     Self {
       states: HashMap::new(),
       versions: HashMap::new(),
+      snapshot_marker: None,
     }
   }
 }
@@ -43,7 +53,11 @@ where
   T: Aggregate,
 {
   pub fn new(states: HashMap<String, T>, versions: HashMap<String, Version>) -> Self {
-    Self { states, versions }
+    Self {
+      states,
+      versions,
+      snapshot_marker: None,
+    }
   }
 
   pub fn get_state<K: AsRef<str>>(&self, id: K) -> Option<&T> {
@@ -57,22 +71,42 @@ where
   pub fn execute_command(
     &mut self,
     command: T::Command,
-  ) -> Result<PersistedEvent<T::Event>, T::Error> {
+  ) -> Result<Vec<PersistedEvent<T::Event>>, T::Error> {
+    self.execute_command_with_metadata(command, Metadata::default())
+  }
+
+  pub fn execute_command_with_metadata(
+    &mut self,
+    command: T::Command,
+    metadata: Metadata,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, T::Error> {
     let id = command.aggregate_id().to_owned();
-    let event = T::handle_command(self.states.get(&id), command)?;
-    let state = T::apply_event(self.states.get(&id).cloned(), event.clone())?;
-    self.states.insert(id.to_owned(), state);
+    let evaluated = evaluate(
+      self.states.get(&id),
+      self.versions.get(&id).cloned().unwrap_or(0),
+      command,
+      metadata,
+    )?;
+    if evaluated.events.is_empty() {
+      return Ok(evaluated.events);
+    }
 
-    let version = self.versions.entry(id.to_owned()).or_insert(0);
-    *version += 1;
+    if let Some(state) = evaluated.state {
+      self.states.insert(id.to_owned(), state);
+    }
+    self.versions.insert(id, evaluated.version);
 
-    let persisted = PersistedEvent {
-      aggregate_id: id,
-      version: *version,
-      event,
-    };
+    Ok(evaluated.events)
+  }
 
-    Ok(persisted)
+  /// Starts evaluating commands without mutating this root.
+  pub fn dry_run(&self) -> DryRun<'_, T> {
+    DryRun {
+      root: self,
+      states: HashMap::new(),
+      versions: HashMap::new(),
+      events: Vec::new(),
+    }
   }
 
   pub fn save_events(&mut self, events: Vec<PersistedEvent<T::Event>>) -> Result<(), T::Error> {
@@ -87,10 +121,131 @@ where
   }
 }
 
+/// Copy-on-write view over an `AggregateRoot`. Commands executed on it see the effects of
+/// earlier ones, but the underlying root is never touched, which suits previews and
+/// validating batches of commands.
+pub struct DryRun<'a, T>
+where
+  T: Aggregate,
+{
+  root: &'a AggregateRoot<T>,
+  states: HashMap<String, T>,
+  versions: HashMap<String, Version>,
+  events: Vec<PersistedEvent<T::Event>>,
+}
+
+impl<'a, T> DryRun<'a, T>
+where
+  T: Aggregate,
+{
+  pub fn get_state<K: AsRef<str>>(&self, id: K) -> Option<&T> {
+    let id = id.as_ref();
+    self.states.get(id).or_else(|| self.root.get_state(id))
+  }
+
+  pub fn get_version<K: AsRef<str>>(&self, id: K) -> Option<&Version> {
+    let id = id.as_ref();
+    self.versions.get(id).or_else(|| self.root.get_version(id))
+  }
+
+  /// Every event produced so far, in execution order.
+  pub fn events(&self) -> &[PersistedEvent<T::Event>] {
+    &self.events
+  }
+
+  pub fn into_events(self) -> Vec<PersistedEvent<T::Event>> {
+    self.events
+  }
+
+  pub fn execute_command(
+    &mut self,
+    command: T::Command,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, T::Error> {
+    self.execute_command_with_metadata(command, Metadata::default())
+  }
+
+  pub fn execute_command_with_metadata(
+    &mut self,
+    command: T::Command,
+    metadata: Metadata,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, T::Error> {
+    let id = command.aggregate_id().to_owned();
+    let evaluated = evaluate(
+      self.get_state(&id),
+      self.get_version(&id).cloned().unwrap_or(0),
+      command,
+      metadata,
+    )?;
+    if evaluated.events.is_empty() {
+      return Ok(evaluated.events);
+    }
+
+    if let Some(state) = evaluated.state {
+      self.states.insert(id.to_owned(), state);
+    }
+    self.versions.insert(id, evaluated.version);
+    self.events.extend(evaluated.events.iter().cloned());
+
+    Ok(evaluated.events)
+  }
+}
+
+struct Evaluated<T>
+where
+  T: Aggregate,
+{
+  events: Vec<PersistedEvent<T::Event>>,
+  state: Option<T>,
+  version: Version,
+}
+
+fn evaluate<T>(
+  state: Option<&T>,
+  version: Version,
+  command: T::Command,
+  metadata: Metadata,
+) -> Result<Evaluated<T>, T::Error>
+where
+  T: Aggregate,
+{
+  let metadata = Metadata {
+    recorded_at: metadata
+      .recorded_at
+      .or_else(|| Some(Utc::now().timestamp())),
+    idempotency_key: command
+      .idempotency_key()
+      .map(str::to_owned)
+      .or(metadata.idempotency_key),
+    ..metadata
+  };
+  let id = command.aggregate_id().to_owned();
+  let events = T::handle_command(state, command)?;
+
+  let mut state = state.cloned();
+  let mut version = version;
+  let mut persisted_events = Vec::with_capacity(events.len());
+  for event in events {
+    state = Some(T::apply_event(state, event.clone())?);
+    version += 1;
+    persisted_events.push(PersistedEvent {
+      aggregate_id: id.to_owned(),
+      version,
+      event,
+      metadata: metadata.clone(),
+    });
+  }
+
+  Ok(Evaluated {
+    events: persisted_events,
+    state,
+    version,
+  })
+}
+
 #[cfg(test)]
 mod test {
   use crate::testing::{Todo, TodoCommand, TodoError, TodoEvent, TodoStatus};
-  use crate::{AggregateRoot, PersistedEvent};
+  use crate::{AggregateRoot, Metadata, PersistedEvent};
 
   #[test]
   fn execute_command_and_returns_persisted_event() {
@@ -104,7 +259,7 @@ mod test {
     let persisted = todo_root.execute_command(command).unwrap();
     assert_eq!(
       persisted,
-      PersistedEvent {
+      vec![PersistedEvent {
         aggregate_id: "todo_0".to_string(),
         version: 1,
         event: TodoEvent::TodoCreated {
@@ -112,8 +267,125 @@ mod test {
           title: "Drink soda".to_string(),
           status: TodoStatus::InProgress,
         },
-      }
+        metadata: persisted[0].metadata.clone(),
+      }]
     );
+    assert!(persisted[0].metadata.recorded_at.is_some());
+  }
+
+  #[test]
+  fn execute_command_can_produce_many_events_with_consecutive_versions() {
+    let mut todo_root: AggregateRoot<Todo> = AggregateRoot::default();
+    todo_root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .unwrap();
+
+    let persisted = todo_root
+      .execute_command(TodoCommand::UpdateTodo {
+        id: "todo_0".to_string(),
+        title: Some("Eat pizza".to_string()),
+        status: Some(TodoStatus::Done),
+        request_id: None,
+      })
+      .unwrap();
+
+    assert_eq!(
+      persisted,
+      vec![
+        PersistedEvent {
+          aggregate_id: "todo_0".to_string(),
+          version: 2,
+          event: TodoEvent::TodoTitleUpdated {
+            title: "Eat pizza".to_string(),
+          },
+          metadata: persisted[0].metadata.clone(),
+        },
+        PersistedEvent {
+          aggregate_id: "todo_0".to_string(),
+          version: 3,
+          event: TodoEvent::TodoStatusUpdated {
+            status: TodoStatus::Done,
+          },
+          metadata: persisted[0].metadata.clone(),
+        },
+      ]
+    );
+    assert_eq!(todo_root.get_version("todo_0").unwrap(), &3);
+    assert_eq!(
+      todo_root.get_state("todo_0").unwrap().status,
+      TodoStatus::Done
+    );
+  }
+
+  #[test]
+  fn execute_command_without_events_is_noop() {
+    let mut todo_root: AggregateRoot<Todo> = AggregateRoot::default();
+    todo_root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .unwrap();
+
+    let update = TodoCommand::UpdateTodo {
+      id: "todo_0".to_string(),
+      title: Some("Eat rice".to_string()),
+      status: None,
+      request_id: None,
+    };
+    assert!(todo_root
+      .execute_command(update.clone())
+      .unwrap()
+      .is_empty());
+    assert!(todo_root.execute_command(update).unwrap().is_empty());
+    assert_eq!(todo_root.get_version("todo_0").unwrap(), &1);
+  }
+
+  #[test]
+  fn execute_command_with_metadata_stamps_every_event() {
+    let mut todo_root: AggregateRoot<Todo> = AggregateRoot::default();
+    todo_root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .unwrap();
+
+    let metadata = Metadata {
+      actor: Some("user_0".to_string()),
+      correlation_id: Some("request_0".to_string()),
+      extra: [("client".to_string(), "cli".to_string())].into(),
+      ..Metadata::default()
+    };
+    let persisted = todo_root
+      .execute_command_with_metadata(
+        TodoCommand::UpdateTodo {
+          id: "todo_0".to_string(),
+          title: Some("Eat pizza".to_string()),
+          status: Some(TodoStatus::Done),
+          request_id: None,
+        },
+        metadata.clone(),
+      )
+      .unwrap();
+
+    assert_eq!(persisted.len(), 2);
+    for event in persisted {
+      assert!(event.metadata.recorded_at.is_some());
+      assert_eq!(
+        event.metadata,
+        Metadata {
+          recorded_at: event.metadata.recorded_at,
+          ..metadata.clone()
+        }
+      );
+    }
   }
 
   #[test]
@@ -206,6 +478,7 @@ mod test {
           title: "Drink soda".to_string(),
           status: TodoStatus::InProgress,
         },
+        metadata: Metadata::default(),
       },
       PersistedEvent {
         aggregate_id: "todo_0".to_string(),
@@ -213,6 +486,7 @@ mod test {
         event: TodoEvent::TodoTitleUpdated {
           title: "Coding".to_string(),
         },
+        metadata: Metadata::default(),
       },
     ];
     let mut root: AggregateRoot<Todo> = AggregateRoot::default();
@@ -225,4 +499,63 @@ mod test {
     assert_eq!(state.status, TodoStatus::InProgress);
     assert_eq!(*version, 2);
   }
+
+  #[test]
+  fn dry_run_should_not_touch_root() {
+    let mut todo_root: AggregateRoot<Todo> = AggregateRoot::default();
+    todo_root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .unwrap();
+
+    let mut dry_run = todo_root.dry_run();
+    dry_run
+      .execute_command(TodoCommand::UpdateTodoTitle {
+        id: "todo_0".to_string(),
+        title: "Eat pizza".to_string(),
+      })
+      .unwrap();
+    dry_run
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo_1".to_string(),
+        title: "Drink soda".to_string(),
+        status: None,
+      })
+      .unwrap();
+    let persisted = dry_run
+      .execute_command(TodoCommand::UpdateTodoStatus {
+        id: "todo_0".to_string(),
+        status: TodoStatus::Done,
+      })
+      .unwrap();
+
+    assert_eq!(persisted[0].version, 3);
+    assert_eq!(dry_run.get_state("todo_0").unwrap().title, "Eat pizza");
+    assert_eq!(dry_run.get_version("todo_1"), Some(&1));
+    let versions: Vec<_> = dry_run.events().iter().map(|x| x.version).collect();
+    assert_eq!(versions, vec![2, 1, 3]);
+
+    assert_eq!(todo_root.get_version("todo_0"), Some(&1));
+    assert_eq!(todo_root.get_state("todo_0").unwrap().title, "Eat rice");
+    assert!(todo_root.get_state("todo_1").is_none());
+  }
+
+  #[test]
+  fn dry_run_should_fail_on_invalid_command_in_batch() {
+    let todo_root: AggregateRoot<Todo> = AggregateRoot::default();
+    let mut dry_run = todo_root.dry_run();
+    let create = TodoCommand::CreateTodo {
+      id: "todo_0".to_string(),
+      title: "Eat rice".to_string(),
+      status: None,
+    };
+
+    dry_run.execute_command(create.clone()).unwrap();
+    let err = dry_run.execute_command(create).unwrap_err();
+    assert_eq!(err, TodoError::AlreadyExists);
+    assert_eq!(dry_run.into_events().len(), 1);
+  }
 }
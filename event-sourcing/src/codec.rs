@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, from_value, to_value, Value};
+
+use crate::shredding::{decrypt_field, encrypt_field};
+use crate::{Event, KeyStore, Metadata, PersistedEvent, ShreddingError, Version};
+
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Transforms the JSON of an event from one schema version into the next one.
+pub type Upcaster = fn(Value) -> Value;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CodecError {
+  #[error("json error: {0}")]
+  JsonError(#[from] serde_json::Error),
+
+  #[error("event schema version {found} is newer than {current}")]
+  UnsupportedSchemaVersion { found: u32, current: u32 },
+
+  #[error("shredding error: {0}")]
+  ShreddingError(#[from] ShreddingError),
+}
+
+/// Envelope of an encoded event, readable without decrypting or upcasting the event.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EventHeader {
+  pub aggregate_id: String,
+  pub version: Version,
+  #[serde(default)]
+  pub metadata: Metadata,
+}
+
+/// Serializes `PersistedEvent`s with their schema version and upcasts older ones on read.
+#[derive(Debug, Clone)]
+pub struct EventCodec<T>
+where
+  T: Event,
+{
+  upcasters: HashMap<u32, Upcaster>,
+  key_store: Option<Arc<dyn KeyStore>>,
+  _event: PhantomData<T>,
+}
+
+impl<T> Default for EventCodec<T>
+where
+  T: Event,
+{
+  fn default() -> Self {
+    Self {
+      upcasters: HashMap::new(),
+      key_store: None,
+      _event: PhantomData,
+    }
+  }
+}
+
+impl<T> EventCodec<T>
+where
+  T: Event + Serialize + DeserializeOwned,
+{
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers the upcaster turning an event of `from_version` into `from_version + 1`.
+  /// Versions without an upcaster are assumed to share the same shape.
+  #[must_use]
+  pub fn upcaster(mut self, from_version: u32, upcaster: Upcaster) -> Self {
+    self.upcasters.insert(from_version, upcaster);
+    self
+  }
+
+  /// Encrypts personal data fields of events with keys from `key_store`.
+  #[must_use]
+  pub fn key_store(mut self, key_store: Arc<dyn KeyStore>) -> Self {
+    self.key_store = Some(key_store);
+    self
+  }
+
+  pub fn encode(&self, persisted: &PersistedEvent<T>) -> Result<String, CodecError> {
+    let mut value = to_value(persisted)?;
+    if let Value::Object(map) = &mut value {
+      map.insert(SCHEMA_VERSION_KEY.to_string(), T::SCHEMA_VERSION.into());
+      if let Some(event) = map.get_mut("event") {
+        self.encrypt(persisted, event)?;
+      }
+    }
+
+    Ok(value.to_string())
+  }
+
+  pub fn decode(&self, raw: &str) -> Result<PersistedEvent<T>, CodecError> {
+    let mut value: Value = from_str(raw)?;
+    if let Value::Object(map) = &mut value {
+      // Events written before schema versions existed are the first version.
+      let found = map
+        .remove(SCHEMA_VERSION_KEY)
+        .and_then(|x| x.as_u64())
+        .map(|x| x as u32)
+        .unwrap_or(1);
+      if found > T::SCHEMA_VERSION {
+        return Err(CodecError::UnsupportedSchemaVersion {
+          found,
+          current: T::SCHEMA_VERSION,
+        });
+      }
+
+      if let Some(Value::Object(event)) = map.get_mut("event") {
+        for field in event.values_mut() {
+          decrypt_field(self.key_store.as_deref(), field)?;
+        }
+      }
+
+      if let Some(event) = map.remove("event") {
+        let event = (found..T::SCHEMA_VERSION).fold(event, |event, version| {
+          match self.upcasters.get(&version) {
+            Some(upcast) => upcast(event),
+            None => event,
+          }
+        });
+        map.insert("event".to_string(), event);
+      }
+    }
+
+    Ok(from_value(value)?)
+  }
+
+  /// Reads the envelope only, for stores scanning many events to find a few.
+  pub fn decode_header(&self, raw: &str) -> Result<EventHeader, CodecError> {
+    Ok(from_str(raw)?)
+  }
+
+  fn encrypt(&self, persisted: &PersistedEvent<T>, event: &mut Value) -> Result<(), CodecError> {
+    let fields = persisted.event.personal_data_fields();
+    if fields.is_empty() {
+      return Ok(());
+    }
+
+    let key_store = self
+      .key_store
+      .as_ref()
+      .ok_or(ShreddingError::MissingKeyStore)?;
+    let subject = persisted
+      .event
+      .data_subject()
+      .unwrap_or(&persisted.aggregate_id);
+    let key = key_store.get_or_create_key(subject)?;
+    for field in fields {
+      if let Some(value) = event.get_mut(*field) {
+        *value = encrypt_field(&key, subject, value)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::{Deserialize, Serialize};
+  use serde_json::{json, Value};
+
+  use crate::testing::TodoEvent;
+  use crate::{CodecError, Event, EventCodec, Metadata, PersistedEvent};
+
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+  #[serde(tag = "name")]
+  enum NoteEvent {
+    NoteWritten { text: String, pinned: bool },
+  }
+
+  impl Event for NoteEvent {
+    const SCHEMA_VERSION: u32 = 3;
+
+    fn name(&self) -> &'static str {
+      "NoteWritten"
+    }
+  }
+
+  fn rename_body_to_text(mut event: Value) -> Value {
+    if let Some(body) = event.as_object_mut().and_then(|x| x.remove("body")) {
+      event["text"] = body;
+    }
+    event
+  }
+
+  fn add_pinned(mut event: Value) -> Value {
+    event["pinned"] = false.into();
+    event
+  }
+
+  #[test]
+  fn should_stamp_schema_version() {
+    let codec = EventCodec::<NoteEvent>::new();
+    let raw = codec
+      .encode(&PersistedEvent {
+        aggregate_id: "note1".to_string(),
+        version: 1,
+        event: NoteEvent::NoteWritten {
+          text: "hello".to_string(),
+          pinned: true,
+        },
+        metadata: Metadata::default(),
+      })
+      .unwrap();
+
+    let value: Value = serde_json::from_str(&raw).unwrap();
+    assert_eq!(value["schema_version"], 3);
+    assert_eq!(codec.decode(&raw).unwrap().version, 1);
+  }
+
+  #[test]
+  fn should_upcast_older_events() {
+    let codec = EventCodec::<NoteEvent>::new()
+      .upcaster(1, rename_body_to_text)
+      .upcaster(2, add_pinned);
+    let legacy = json!({
+      "aggregate_id": "note1",
+      "version": 1,
+      "event": { "name": "NoteWritten", "body": "hello" },
+    });
+
+    let persisted = codec.decode(&legacy.to_string()).unwrap();
+    assert_eq!(
+      persisted.event,
+      NoteEvent::NoteWritten {
+        text: "hello".to_string(),
+        pinned: false,
+      }
+    );
+  }
+
+  #[test]
+  fn should_reject_events_from_newer_schema() {
+    let codec = EventCodec::<TodoEvent>::new();
+    let raw = json!({
+      "schema_version": 2,
+      "aggregate_id": "todo1",
+      "version": 1,
+      "event": { "name": "TodoTitleUpdated", "title": "Eat pizza" },
+    });
+
+    let err = codec.decode(&raw.to_string()).unwrap_err();
+    assert!(matches!(
+      err,
+      CodecError::UnsupportedSchemaVersion {
+        found: 2,
+        current: 1
+      }
+    ));
+  }
+}
@@ -1,4 +1,79 @@
 pub trait Command: Send + Sync + Clone {
   fn name(&self) -> &'static str;
   fn aggregate_id(&self) -> &str;
+
+  /// Identifies retries of the same request. Dispatching a command whose key already produced
+  /// events for the aggregate returns those events instead of executing it again.
+  fn idempotency_key(&self) -> Option<&str> {
+    None
+  }
+}
+
+/// Field types accepted by `#[command(idempotency_key)]`.
+#[doc(hidden)]
+pub trait AsIdempotencyKey {
+  fn as_idempotency_key(&self) -> Option<&str>;
+}
+
+impl AsIdempotencyKey for String {
+  fn as_idempotency_key(&self) -> Option<&str> {
+    Some(self)
+  }
+}
+
+impl AsIdempotencyKey for Option<String> {
+  fn as_idempotency_key(&self) -> Option<&str> {
+    self.as_deref()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::testing::TodoCommand;
+  use crate::Command;
+
+  #[derive(Debug, Clone, Command)]
+  enum NoteCommand {
+    CreateNote {
+      #[command(aggregate_id)]
+      note_id: String,
+      #[command(idempotency_key)]
+      request_id: Option<String>,
+    },
+    ArchiveNote(#[command(aggregate_id)] String),
+  }
+
+  #[derive(Debug, Clone, Command)]
+  struct DeleteNote {
+    id: String,
+  }
+
+  #[test]
+  fn derived_aggregate_id_should_use_marked_field_or_id() {
+    let command = TodoCommand::UpdateTodoTitle {
+      id: "todo1".to_string(),
+      title: "Todo".to_string(),
+    };
+    assert_eq!(command.name(), "UpdateTodoTitle");
+    assert_eq!(command.aggregate_id(), "todo1");
+
+    let command = NoteCommand::CreateNote {
+      note_id: "note1".to_string(),
+      request_id: Some("request1".to_string()),
+    };
+    assert_eq!(command.name(), "CreateNote");
+    assert_eq!(command.aggregate_id(), "note1");
+    assert_eq!(command.idempotency_key(), Some("request1"));
+    assert_eq!(
+      NoteCommand::ArchiveNote("note2".to_string()).aggregate_id(),
+      "note2"
+    );
+
+    let command = DeleteNote {
+      id: "note3".to_string(),
+    };
+    assert_eq!(command.name(), "DeleteNote");
+    assert_eq!(command.aggregate_id(), "note3");
+    assert_eq!(command.idempotency_key(), None);
+  }
 }
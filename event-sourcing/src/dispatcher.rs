@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use futures::StreamExt;
+
+use crate::{
+  load_root, Aggregate, AggregateRoot, Command, Error, Event, Eventstore, LoadReport, Metadata,
+  Middleware, MiddlewareChain, PersistedEvent, Snapshot, SnapshotPolicy, Timestamp, Version,
+  VersionSelect,
+};
+
+/// Executes commands against an `AggregateRoot`, persists the produced events and saves a
+/// snapshot as decided by its `SnapshotPolicy`. The root only changes when the events were
+/// appended. A failed snapshot save does not fail the dispatch, see `take_snapshot_error`.
+pub struct Dispatcher<T, E, S>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+  S: Snapshot<T>,
+{
+  root: AggregateRoot<T>,
+  eventstore: E,
+  snapshot: S,
+  policy: SnapshotPolicy,
+  middleware: MiddlewareChain<T>,
+  unsaved_events: u64,
+  last_snapshot_at: Timestamp,
+  snapshot_error: Option<S::Error>,
+  idempotency_keys: HashMap<String, KeyIndex>,
+}
+
+/// Idempotency keys recorded on the events of one aggregate up to `version`, each with the
+/// first and last version it produced.
+#[derive(Default)]
+struct KeyIndex {
+  version: Version,
+  keys: HashMap<String, (Version, Version)>,
+}
+
+impl KeyIndex {
+  fn insert<E: Event>(&mut self, event: &PersistedEvent<E>) {
+    if let Some(key) = &event.metadata.idempotency_key {
+      self
+        .keys
+        .entry(key.to_owned())
+        .and_modify(|x| x.1 = event.version)
+        .or_insert((event.version, event.version));
+    }
+    self.version = event.version;
+  }
+}
+
+impl<T, E, S> Dispatcher<T, E, S>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+  S: Snapshot<T>,
+{
+  pub fn new(root: AggregateRoot<T>, eventstore: E, snapshot: S) -> Self {
+    Self {
+      root,
+      eventstore,
+      snapshot,
+      policy: SnapshotPolicy::default(),
+      middleware: MiddlewareChain::default(),
+      unsaved_events: 0,
+      last_snapshot_at: Utc::now().timestamp(),
+      snapshot_error: None,
+      idempotency_keys: HashMap::new(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_snapshot_policy(self, policy: SnapshotPolicy) -> Self {
+    Self { policy, ..self }
+  }
+
+  /// Adds a layer around every dispatched command, see `MiddlewareChain`.
+  #[must_use]
+  pub fn with_middleware<M>(mut self, middleware: M) -> Self
+  where
+    M: Middleware<T> + 'static,
+  {
+    self.middleware.push(middleware);
+    self
+  }
+
+  pub fn middleware(&self) -> &MiddlewareChain<T> {
+    &self.middleware
+  }
+
+  /// Loads the root like `load_aggregate`, which saves the snapshot regardless of the policy.
+  pub async fn load(
+    eventstore: E,
+    snapshot: S,
+  ) -> Result<Self, Error<T::Error, E::Error, S::Error>> {
+    let (dispatcher, _) = Self::load_with_report(eventstore, snapshot).await?;
+    Ok(dispatcher)
+  }
+
+  pub async fn load_with_report(
+    eventstore: E,
+    snapshot: S,
+  ) -> Result<(Self, LoadReport), Error<T::Error, E::Error, S::Error>> {
+    let (root, report) = load_root(&eventstore, &snapshot).await?;
+    Ok((Self::new(root, eventstore, snapshot), report))
+  }
+
+  pub fn root(&self) -> &AggregateRoot<T> {
+    &self.root
+  }
+
+  pub fn eventstore(&self) -> &E {
+    &self.eventstore
+  }
+
+  pub fn snapshot(&self) -> &S {
+    &self.snapshot
+  }
+
+  /// Takes the error of the last snapshot save that failed after its events were appended.
+  /// The snapshot is retried on the next dispatch.
+  pub fn take_snapshot_error(&mut self) -> Option<S::Error> {
+    self.snapshot_error.take()
+  }
+
+  pub async fn dispatch(
+    &mut self,
+    command: T::Command,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, Error<T::Error, E::Error, S::Error>> {
+    self
+      .dispatch_with_metadata(command, Metadata::default())
+      .await
+  }
+
+  pub async fn dispatch_with_metadata(
+    &mut self,
+    command: T::Command,
+    mut metadata: Metadata,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, Error<T::Error, E::Error, S::Error>> {
+    // Runs first, so a replayed idempotency key is authorized and validated like any command.
+    self
+      .middleware
+      .before(&command, &mut metadata)
+      .map_err(Error::AggregateError)?;
+
+    let id = command.aggregate_id().to_owned();
+    let key = command
+      .idempotency_key()
+      .or(metadata.idempotency_key.as_deref());
+    if let Some(key) = key {
+      let produced = self
+        .produced_by(&id, key)
+        .await
+        .map_err(Error::EventstoreError)?;
+      if !produced.is_empty() {
+        return Ok(produced);
+      }
+    }
+
+    let prev_state = self.root.states.get(&id).cloned();
+    let prev_version = self.root.versions.get(&id).cloned();
+
+    let events = self
+      .evaluate(command, metadata, prev_version.unwrap_or(0))
+      .map_err(Error::AggregateError)?;
+    if events.is_empty() {
+      return Ok(events);
+    }
+    if let Err(e) = self.root.save_events(events.clone()) {
+      self.rollback(id, prev_state, prev_version);
+      return Err(Error::AggregateError(e));
+    }
+
+    let appended = self
+      .eventstore
+      .append_expected(id.to_owned(), prev_version.unwrap_or(0), events.clone())
+      .await;
+    if let Err(e) = appended {
+      self.rollback(id, prev_state, prev_version);
+      return Err(e.into());
+    }
+
+    self.unsaved_events += events.len() as u64;
+    let now = Utc::now().timestamp();
+    if self
+      .policy
+      .should_snapshot(self.unsaved_events, self.last_snapshot_at, now)
+    {
+      // The events are stored at this point, so reporting an error would make callers retry
+      // and append them twice.
+      if let Err(e) = self.save_snapshot(now).await {
+        self.snapshot_error = Some(e);
+      }
+    }
+
+    Ok(events)
+  }
+
+  /// Saves events applied since the last snapshot, unless the policy is `Never`.
+  pub async fn shutdown(&mut self) -> Result<(), Error<T::Error, E::Error, S::Error>> {
+    if self.policy.should_snapshot_on_shutdown(self.unsaved_events) {
+      self
+        .save_snapshot(Utc::now().timestamp())
+        .await
+        .map_err(Error::SnapshotError)?;
+    }
+
+    Ok(())
+  }
+
+  async fn save_snapshot(&mut self, now: Timestamp) -> Result<(), S::Error> {
+    self.snapshot.save(self.root.clone()).await?;
+    self.unsaved_events = 0;
+    self.last_snapshot_at = now;
+
+    Ok(())
+  }
+
+  /// Produces the events of `command` without applying them, so the `after` middleware can
+  /// decorate them first. Aggregate ids and versions are stamped again afterwards, as the
+  /// state is applied to this aggregate at these versions whatever middleware does.
+  fn evaluate(
+    &self,
+    command: T::Command,
+    metadata: Metadata,
+    version: Version,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, T::Error> {
+    let id = command.aggregate_id().to_owned();
+    let mut events = self
+      .root
+      .dry_run()
+      .execute_command_with_metadata(command.clone(), metadata)?;
+    self.middleware.after(&command, &mut events)?;
+    for (event, version) in events.iter_mut().zip(version + 1..) {
+      event.aggregate_id = id.to_owned();
+      event.version = version;
+    }
+
+    Ok(events)
+  }
+
+  /// Events of `aggregate_id` recorded with the idempotency key `key`. Keys are indexed as
+  /// they are looked up, so each event of the aggregate is only read once.
+  async fn produced_by(
+    &mut self,
+    aggregate_id: &str,
+    key: &str,
+  ) -> Result<Vec<PersistedEvent<T::Event>>, E::Error> {
+    let index = self
+      .idempotency_keys
+      .entry(aggregate_id.to_owned())
+      .or_default();
+    let mut events = self.eventstore.stream(
+      aggregate_id.to_owned(),
+      VersionSelect::From(index.version + 1),
+    );
+    while let Some(event) = events.next().await {
+      index.insert(&event?);
+    }
+
+    let (from, to) = match index.keys.get(key) {
+      Some(x) => *x,
+      None => return Ok(Vec::new()),
+    };
+    let produced = self
+      .eventstore
+      .read(aggregate_id.to_owned(), VersionSelect::Range(from, to))
+      .await?
+      .into_iter()
+      .filter(|x| x.metadata.idempotency_key.as_deref() == Some(key))
+      .collect();
+
+    Ok(produced)
+  }
+
+  fn rollback(&mut self, id: String, state: Option<T>, version: Option<Version>) {
+    match state {
+      Some(x) => self.root.states.insert(id.to_owned(), x),
+      None => self.root.states.remove(&id),
+    };
+    match version {
+      Some(x) => self.root.versions.insert(id, x),
+      None => self.root.versions.remove(&id),
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+
+  use crate::testing::{
+    create_todo, dispatcher, InMemoryEventstore, InMemorySnapshot, Todo, TodoCommand, TodoError,
+    TodoStatus,
+  };
+  use crate::{
+    AggregateRoot, Dispatcher, Error, Eventstore, Metadata, Middleware, Snapshot, SnapshotPolicy,
+    VersionSelect,
+  };
+
+  #[tokio::test]
+  async fn should_execute_persist_and_snapshot() {
+    let eventstore = InMemoryEventstore::default();
+    let snapshot = InMemorySnapshot::default();
+    let mut dispatcher = Dispatcher::load(eventstore.clone(), snapshot.clone())
+      .await
+      .unwrap();
+
+    let events = dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(dispatcher.root().get_version("todo1"), Some(&1));
+
+    let stored = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(stored, events);
+    let saved: AggregateRoot<Todo> = snapshot.saved().unwrap();
+    assert_eq!(saved.get_version("todo1"), Some(&1));
+  }
+
+  #[tokio::test]
+  async fn should_rollback_when_append_fails() {
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+
+    // Another writer updates the same todo behind our back.
+    let mut other: AggregateRoot<Todo> = AggregateRoot::default();
+    other
+      .save_events(
+        eventstore
+          .read("todo1".to_string(), VersionSelect::All)
+          .await
+          .unwrap(),
+      )
+      .unwrap();
+    let events = other
+      .execute_command(TodoCommand::UpdateTodoStatus {
+        id: "todo1".to_string(),
+        status: TodoStatus::Done,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let err = dispatcher
+      .dispatch(TodoCommand::UpdateTodoTitle {
+        id: "todo1".to_string(),
+        title: "Eat pizza".to_string(),
+      })
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      Error::VersionConflict {
+        expected: 1,
+        actual: 2,
+        ..
+      }
+    ));
+    assert_eq!(dispatcher.root().get_version("todo1"), Some(&1));
+    assert_eq!(
+      dispatcher.root().get_state("todo1").unwrap().title,
+      "Drink coffee"
+    );
+
+    let err = dispatcher.dispatch(create_todo("todo1")).await.unwrap_err();
+    assert!(matches!(err, Error::AggregateError(_)));
+  }
+
+  #[tokio::test]
+  async fn should_not_append_commands_rejected_by_middleware() {
+    struct DenyAll;
+    impl Middleware<Todo> for DenyAll {
+      fn before(&self, _command: &TodoCommand, _metadata: &mut Metadata) -> Result<(), TodoError> {
+        Err(TodoError::Unauthorized)
+      }
+    }
+
+    let mut dispatcher = dispatcher().with_middleware(DenyAll);
+    let eventstore = dispatcher.eventstore().clone();
+
+    let err = dispatcher.dispatch(create_todo("todo1")).await.unwrap_err();
+    assert!(matches!(
+      err,
+      Error::AggregateError(TodoError::Unauthorized)
+    ));
+    assert!(eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap()
+      .is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_return_appended_events_when_snapshot_save_fails() {
+    struct BrokenSnapshot;
+
+    #[async_trait]
+    impl Snapshot<Todo> for BrokenSnapshot {
+      type Error = &'static str;
+
+      async fn load(&self) -> Result<AggregateRoot<Todo>, Self::Error> {
+        Ok(AggregateRoot::default())
+      }
+
+      async fn save(&self, _root: AggregateRoot<Todo>) -> Result<(), Self::Error> {
+        Err("disk full")
+      }
+    }
+
+    let eventstore = InMemoryEventstore::default();
+    let mut dispatcher = Dispatcher::new(
+      AggregateRoot::<Todo>::default(),
+      eventstore.clone(),
+      BrokenSnapshot,
+    );
+
+    let events = dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(dispatcher.take_snapshot_error(), Some("disk full"));
+    assert_eq!(dispatcher.take_snapshot_error(), None);
+    assert_eq!(
+      eventstore
+        .read("todo1".to_string(), VersionSelect::All)
+        .await
+        .unwrap(),
+      events
+    );
+    assert!(matches!(
+      dispatcher.shutdown().await,
+      Err(Error::SnapshotError("disk full"))
+    ));
+  }
+
+  #[tokio::test]
+  async fn should_snapshot_by_policy() {
+    let snapshot = InMemorySnapshot::default();
+    let mut dispatcher = Dispatcher::new(
+      AggregateRoot::<Todo>::default(),
+      InMemoryEventstore::default(),
+      snapshot.clone(),
+    )
+    .with_snapshot_policy(SnapshotPolicy::EveryEvents(2));
+
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+    assert!(snapshot.saved().is_none());
+    dispatcher.dispatch(create_todo("todo2")).await.unwrap();
+    assert_eq!(snapshot.saved().unwrap().versions.len(), 2);
+
+    dispatcher.dispatch(create_todo("todo3")).await.unwrap();
+    assert_eq!(snapshot.saved().unwrap().versions.len(), 2);
+    dispatcher.shutdown().await.unwrap();
+    assert_eq!(snapshot.saved().unwrap().versions.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn should_not_snapshot_with_never_policy() {
+    let snapshot = InMemorySnapshot::default();
+    let mut dispatcher = Dispatcher::new(
+      AggregateRoot::<Todo>::default(),
+      InMemoryEventstore::default(),
+      snapshot.clone(),
+    )
+    .with_snapshot_policy(SnapshotPolicy::Never);
+
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+    dispatcher.shutdown().await.unwrap();
+    assert!(snapshot.saved().is_none());
+  }
+
+  #[tokio::test]
+  async fn should_return_produced_events_for_retried_command() {
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+
+    let update = TodoCommand::UpdateTodo {
+      id: "todo1".to_string(),
+      title: Some("Eat pizza".to_string()),
+      status: Some(TodoStatus::Done),
+      request_id: Some("request1".to_string()),
+    };
+    let events = dispatcher.dispatch(update.clone()).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+      events[0].metadata.idempotency_key.as_deref(),
+      Some("request1")
+    );
+
+    let retried = dispatcher.dispatch(update).await.unwrap();
+    assert_eq!(retried, events);
+    assert_eq!(dispatcher.root().get_version("todo1"), Some(&3));
+    let stored = eventstore
+      .read("todo1".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(stored.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn should_index_idempotency_keys_incrementally() {
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+    let update = |request_id: &str| TodoCommand::UpdateTodo {
+      id: "todo1".to_string(),
+      title: Some(format!("Eat pizza for {}", request_id)),
+      status: Some(TodoStatus::Done),
+      request_id: Some(request_id.to_string()),
+    };
+    dispatcher.dispatch(update("request1")).await.unwrap();
+    assert_eq!(dispatcher.idempotency_keys["todo1"].version, 1);
+
+    // Another writer handles a command the dispatcher is then asked to retry.
+    let mut other = dispatcher.root().clone();
+    let events = other.execute_command(update("request2")).unwrap();
+    eventstore.append(events.clone()).await.unwrap();
+
+    let retried = dispatcher.dispatch(update("request2")).await.unwrap();
+    assert_eq!(retried, events);
+    let index = &dispatcher.idempotency_keys["todo1"];
+    assert_eq!(index.version, 4);
+    assert_eq!(index.keys["request1"], (2, 3));
+    assert_eq!(index.keys["request2"], (4, 4));
+  }
+}
@@ -1,9 +1,42 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-use crate::Version;
+use crate::{Position, Timestamp, Version};
 
 pub trait Event: Send + Sync + Clone {
+  /// Bumped whenever the serialized shape of the event changes. See `EventCodec`.
+  const SCHEMA_VERSION: u32 = 1;
+
   fn name(&self) -> &'static str;
+
+  /// Fields holding personal data, which `EventCodec` encrypts with the key of the data
+  /// subject. Their types must implement `PersonalData`, as they read as `REDACTED` once the
+  /// key is deleted.
+  fn personal_data_fields(&self) -> &'static [&'static str] {
+    &[]
+  }
+
+  /// Whose personal data the event holds. Defaults to the aggregate.
+  fn data_subject(&self) -> Option<&str> {
+    None
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Metadata {
+  #[serde(default)]
+  pub recorded_at: Option<Timestamp>,
+  #[serde(default)]
+  pub actor: Option<String>,
+  #[serde(default)]
+  pub correlation_id: Option<String>,
+  #[serde(default)]
+  pub causation_id: Option<String>,
+  #[serde(default)]
+  pub idempotency_key: Option<String>,
+  #[serde(default)]
+  pub extra: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -14,4 +47,69 @@ where
   pub aggregate_id: String,
   pub version: Version,
   pub event: T,
+  #[serde(default)]
+  pub metadata: Metadata,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PositionedEvent<T>
+where
+  T: Event,
+{
+  pub position: Position,
+  pub persisted: PersistedEvent<T>,
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::to_value;
+
+  use super::*;
+  use crate::testing::{TodoEvent, TodoStatus};
+  use crate::Event;
+
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Event)]
+  #[serde(tag = "name", rename_all = "snake_case")]
+  #[event(schema_version = 2)]
+  enum NoteEvent {
+    NoteCreated {
+      id: String,
+    },
+    #[serde(rename = "note_removed")]
+    NoteDeleted,
+  }
+
+  #[test]
+  fn derived_name_should_match_serde_tag() {
+    let events = vec![
+      TodoEvent::TodoCreated {
+        id: "todo1".to_string(),
+        title: "Todo".to_string(),
+        status: TodoStatus::Todo,
+      },
+      TodoEvent::TodoTitleUpdated {
+        title: "Todo".to_string(),
+      },
+    ];
+    for event in events {
+      assert_eq!(to_value(&event).unwrap()["name"], event.name());
+    }
+
+    let event = NoteEvent::NoteCreated {
+      id: "note1".to_string(),
+    };
+    assert_eq!(event.name(), "note_created");
+    assert_eq!(to_value(&event).unwrap()["name"], event.name());
+    assert_eq!(NoteEvent::NoteDeleted.name(), "note_removed");
+    assert_eq!(
+      to_value(NoteEvent::NoteDeleted).unwrap()["name"],
+      "note_removed"
+    );
+  }
+
+  #[test]
+  fn derived_schema_version_should_be_set_from_attribute() {
+    assert_eq!(NoteEvent::SCHEMA_VERSION, 2);
+    assert_eq!(TodoEvent::SCHEMA_VERSION, 1);
+  }
 }
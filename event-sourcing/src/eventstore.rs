@@ -1,11 +1,97 @@
+use std::collections::HashSet;
+
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
 
-use crate::{Event, PersistedEvent, Version};
+use crate::{Event, PersistedEvent, Position, PositionedEvent, Timestamp, Version};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VersionSelect {
   All,
+  /// Versions greater than or equal to the given one.
   From(Version),
+  /// Versions less than or equal to the given one.
+  UpTo(Version),
+  /// Versions between both bounds, inclusive.
+  Range(Version, Version),
+  /// The given number of latest versions.
+  Last(u64),
+}
+
+impl VersionSelect {
+  /// Whether `version` is within the bounds. `Last` depends on the stream head, so it matches
+  /// every version.
+  pub fn contains(&self, version: Version) -> bool {
+    match *self {
+      VersionSelect::All | VersionSelect::Last(_) => true,
+      VersionSelect::From(from) => version >= from,
+      VersionSelect::UpTo(to) => version <= to,
+      VersionSelect::Range(from, to) => version >= from && version <= to,
+    }
+  }
+
+  pub fn lower_bound(&self) -> Option<Version> {
+    match *self {
+      VersionSelect::From(from) | VersionSelect::Range(from, _) => Some(from),
+      _ => None,
+    }
+  }
+
+  pub fn upper_bound(&self) -> Option<Version> {
+    match *self {
+      VersionSelect::UpTo(to) | VersionSelect::Range(_, to) => Some(to),
+      _ => None,
+    }
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AppendError<E> {
+  #[error("version conflict on '{aggregate_id}': expected {expected}, actual {actual}")]
+  VersionConflict {
+    aggregate_id: String,
+    expected: Version,
+    actual: Version,
+  },
+
+  #[error(
+    "expected '{aggregate_id}' at version {version}, found '{found_aggregate_id}' at version {found_version}"
+  )]
+  UnexpectedEvent {
+    aggregate_id: String,
+    version: Version,
+    found_aggregate_id: String,
+    found_version: Version,
+  },
+
+  #[error("eventstore error: {0}")]
+  EventstoreError(#[source] E),
+}
+
+impl<E> AppendError<E> {
+  /// Checks that `events` all belong to `aggregate_id` and continue it right after `expected`
+  /// without gaps, so `append_expected` cannot write around its own version check.
+  pub fn check_events<T>(
+    aggregate_id: &str,
+    expected: Version,
+    events: &[PersistedEvent<T>],
+  ) -> Result<(), Self>
+  where
+    T: Event,
+  {
+    for (version, event) in (expected + 1..).zip(events) {
+      if event.aggregate_id != aggregate_id || event.version != version {
+        return Err(AppendError::UnexpectedEvent {
+          aggregate_id: aggregate_id.to_owned(),
+          version,
+          found_aggregate_id: event.aggregate_id.to_owned(),
+          found_version: event.version,
+        });
+      }
+    }
+
+    Ok(())
+  }
 }
 
 #[async_trait]
@@ -19,5 +105,91 @@ pub trait Eventstore: Send + Sync {
     select: VersionSelect,
   ) -> Result<Vec<PersistedEvent<Self::Event>>, Self::Error>;
 
+  /// Same as `read`, but yields events one by one. Stores holding events outside of memory
+  /// should override this to avoid loading the whole stream at once.
+  fn stream(
+    &self,
+    aggregate_id: String,
+    select: VersionSelect,
+  ) -> BoxStream<'_, Result<PersistedEvent<Self::Event>, Self::Error>> {
+    stream::once(self.read(aggregate_id, select))
+      .flat_map(|events| match events {
+        Ok(events) => stream::iter(events.into_iter().map(Ok)).boxed(),
+        Err(e) => stream::once(async { Err(e) }).boxed(),
+      })
+      .boxed()
+  }
+
   async fn append(&self, events: Vec<PersistedEvent<Self::Event>>) -> Result<(), Self::Error>;
+
+  /// Appends `events` only when the stored version of `aggregate_id` equals `expected`
+  /// (`0` for an aggregate without events), atomically with respect to other writers.
+  async fn append_expected(
+    &self,
+    aggregate_id: String,
+    expected: Version,
+    events: Vec<PersistedEvent<Self::Event>>,
+  ) -> Result<(), AppendError<Self::Error>>;
+
+  /// Reads events of every aggregate in append order, starting right after `after`.
+  /// Positions start at `1`, so `0` reads the whole stream.
+  async fn read_all(
+    &self,
+    after: Position,
+  ) -> Result<Vec<PositionedEvent<Self::Event>>, Self::Error>;
+
+  /// Lists the id of every aggregate with at least one event, in order of first append.
+  async fn aggregate_ids(&self) -> Result<Vec<String>, Self::Error> {
+    let mut seen = HashSet::new();
+    let ids = self
+      .read_all(0)
+      .await?
+      .into_iter()
+      .map(|x| x.persisted.aggregate_id)
+      .filter(|id| seen.insert(id.clone()))
+      .collect();
+
+    Ok(ids)
+  }
+
+  /// Marks the current end of the store, recorded on a root when it is loaded. `None` when the
+  /// store cannot list aggregates from a mark.
+  async fn snapshot_marker(&self) -> Result<Option<String>, Self::Error> {
+    Ok(None)
+  }
+
+  /// Like `aggregate_ids`, but may only list the aggregates with events appended after `marker`,
+  /// as returned by `snapshot_marker`. Falls back to every id when the marker is unknown.
+  async fn aggregate_ids_since_snapshot(&self, _marker: &str) -> Result<Vec<String>, Self::Error> {
+    self.aggregate_ids().await
+  }
+
+  /// Resolves the version `aggregate_id` had at `at`, or `0` when it did not exist yet.
+  /// Events recorded without a timestamp are treated as older than any timestamp.
+  async fn version_at(&self, aggregate_id: String, at: Timestamp) -> Result<Version, Self::Error> {
+    let version = self
+      .read(aggregate_id, VersionSelect::All)
+      .await?
+      .into_iter()
+      .take_while(|x| x.metadata.recorded_at.is_none_or(|t| t <= at))
+      .last()
+      .map(|x| x.version)
+      .unwrap_or(0);
+
+    Ok(version)
+  }
+
+  /// Resolves the last global position recorded at `at`, or `0` when there was no event yet.
+  async fn position_at(&self, at: Timestamp) -> Result<Position, Self::Error> {
+    let position = self
+      .read_all(0)
+      .await?
+      .into_iter()
+      .take_while(|x| x.persisted.metadata.recorded_at.is_none_or(|t| t <= at))
+      .last()
+      .map(|x| x.position)
+      .unwrap_or(0);
+
+    Ok(position)
+  }
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read, rename, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::{Aggregate, AggregateRoot, Snapshot, SnapshotInvalidation, Version};
+
+/// Bumped whenever the layout of snapshot files changes.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+const DEFAULT_FILE_NAME: &str = "snapshot";
+
+#[derive(thiserror::Error, Debug)]
+pub enum FsSnapshotError {
+  #[error("io error: {0}")]
+  IoError(#[source] io::Error),
+
+  #[error("json parse error: {0}")]
+  JsonParseError(#[source] serde_json::Error),
+
+  #[error("unsupported snapshot format version: {0}")]
+  UnsupportedFormatVersion(u32),
+
+  #[error("snapshot version mismatch: stored {stored}, expected {expected}")]
+  VersionMismatch { stored: u32, expected: u32 },
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+  format_version: u32,
+  #[serde(default = "default_snapshot_version")]
+  snapshot_version: u32,
+}
+
+fn default_snapshot_version() -> u32 {
+  1
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+struct SnapshotFile<T> {
+  format_version: u32,
+  snapshot_version: u32,
+  states: HashMap<String, T>,
+  versions: HashMap<String, Version>,
+  #[serde(default)]
+  snapshot_marker: Option<String>,
+}
+
+/// Stores the whole aggregate root as a single JSON file. A missing file loads as an empty
+/// root.
+#[derive(Debug, Clone)]
+pub struct FsSnapshot<T>
+where
+  T: Aggregate,
+{
+  file_path: PathBuf,
+  _aggregate: PhantomData<T>,
+}
+
+impl<T> FsSnapshot<T>
+where
+  T: Aggregate,
+{
+  pub fn new(dir: &Path) -> Self {
+    Self {
+      file_path: dir.join(DEFAULT_FILE_NAME),
+      _aggregate: PhantomData,
+    }
+  }
+
+  #[must_use]
+  pub fn with_file_name(self, file_name: &str) -> Self {
+    let file_path = self.file_path.with_file_name(file_name);
+    Self { file_path, ..self }
+  }
+
+  pub fn file_path(&self) -> &Path {
+    &self.file_path
+  }
+
+  fn temp_path(&self) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(self.file_path.file_name().unwrap_or_default());
+    file_name.push(".tmp");
+    self.file_path.with_file_name(file_name)
+  }
+}
+
+#[async_trait]
+impl<T> Snapshot<T> for FsSnapshot<T>
+where
+  T: Aggregate + Serialize + DeserializeOwned,
+{
+  type Error = FsSnapshotError;
+
+  async fn load(&self) -> Result<AggregateRoot<T>, Self::Error> {
+    let raw = match read(&self.file_path).await {
+      Ok(x) => x,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(AggregateRoot::default()),
+      Err(e) => return Err(FsSnapshotError::IoError(e)),
+    };
+
+    let header: SnapshotHeader =
+      serde_json::from_slice(&raw).map_err(FsSnapshotError::JsonParseError)?;
+    if header.format_version != SNAPSHOT_FORMAT_VERSION {
+      return Err(FsSnapshotError::UnsupportedFormatVersion(
+        header.format_version,
+      ));
+    }
+    if header.snapshot_version != T::SNAPSHOT_VERSION {
+      return Err(FsSnapshotError::VersionMismatch {
+        stored: header.snapshot_version,
+        expected: T::SNAPSHOT_VERSION,
+      });
+    }
+
+    let file: SnapshotFile<T> =
+      serde_json::from_slice(&raw).map_err(FsSnapshotError::JsonParseError)?;
+
+    let mut root = AggregateRoot::new(file.states, file.versions);
+    root.snapshot_marker = file.snapshot_marker;
+
+    Ok(root)
+  }
+
+  async fn save(&self, root: AggregateRoot<T>) -> Result<(), Self::Error> {
+    let file = SnapshotFile {
+      format_version: SNAPSHOT_FORMAT_VERSION,
+      snapshot_version: T::SNAPSHOT_VERSION,
+      states: root.states,
+      versions: root.versions,
+      snapshot_marker: root.snapshot_marker,
+    };
+    let raw = serde_json::to_vec(&file).map_err(FsSnapshotError::JsonParseError)?;
+
+    // Written next to the target and renamed over it, so readers never see a partial file.
+    let temp_path = self.temp_path();
+    let mut temp = File::create(&temp_path)
+      .await
+      .map_err(FsSnapshotError::IoError)?;
+    temp
+      .write_all(&raw)
+      .await
+      .map_err(FsSnapshotError::IoError)?;
+    temp.sync_all().await.map_err(FsSnapshotError::IoError)?;
+    rename(&temp_path, &self.file_path)
+      .await
+      .map_err(FsSnapshotError::IoError)?;
+
+    Ok(())
+  }
+
+  fn invalidation(&self, error: &Self::Error) -> Option<SnapshotInvalidation> {
+    match error {
+      FsSnapshotError::VersionMismatch { stored, expected } => {
+        Some(SnapshotInvalidation::VersionMismatch {
+          stored: *stored,
+          expected: *expected,
+        })
+      }
+      FsSnapshotError::JsonParseError(_) | FsSnapshotError::UnsupportedFormatVersion(_) => {
+        Some(SnapshotInvalidation::Unreadable(error.to_string()))
+      }
+      FsSnapshotError::IoError(_) => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use chrono::Utc;
+  use geeks_git_testing::FixtureRepository;
+
+  use super::*;
+  use crate::testing::{Todo, TodoStatus};
+
+  fn todo_root() -> AggregateRoot<Todo> {
+    let todo = Todo {
+      id: "todo1".to_string(),
+      title: "Eat pizza".to_string(),
+      status: TodoStatus::Done,
+      created_at: Utc::now().timestamp(),
+      updated_at: Utc::now().timestamp(),
+    };
+    AggregateRoot::new(
+      HashMap::from([("todo1".to_string(), todo)]),
+      HashMap::from([("todo1".to_string(), 1)]),
+    )
+  }
+
+  #[tokio::test]
+  async fn should_load_empty_root_without_snapshot_file() {
+    let fixture = FixtureRepository::setup();
+    let snapshot = FsSnapshot::<Todo>::new(&fixture.path);
+
+    let root = snapshot.load().await.unwrap();
+    assert!(root.states.is_empty());
+    assert!(root.versions.is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_save_and_load_with_file_name() {
+    let fixture = FixtureRepository::setup();
+    let snapshot = FsSnapshot::<Todo>::new(&fixture.path).with_file_name("todos.json");
+    snapshot.save(todo_root()).await.unwrap();
+
+    assert!(fixture.path.join("todos.json").exists());
+    assert!(!snapshot.temp_path().exists());
+    let root = snapshot.load().await.unwrap();
+    assert_eq!(root.get_state("todo1").unwrap().title, "Eat pizza");
+    assert_eq!(root.get_version("todo1"), Some(&1));
+  }
+
+  #[tokio::test]
+  async fn should_reject_unsupported_format_version() {
+    let fixture = FixtureRepository::setup();
+    let snapshot = FsSnapshot::<Todo>::new(&fixture.path);
+    std::fs::write(
+      snapshot.file_path(),
+      r#"{"format_version":99,"states":{},"versions":{}}"#,
+    )
+    .unwrap();
+
+    let err = snapshot.load().await.unwrap_err();
+    assert!(matches!(err, FsSnapshotError::UnsupportedFormatVersion(99)));
+  }
+}
@@ -1,20 +1,47 @@
 extern crate core;
+extern crate self as geeks_event_sourcing;
 
-pub use crate::aggregate::{Aggregate, AggregateRoot};
-pub use crate::command::Command;
-pub use crate::event::{Event, PersistedEvent};
+use std::collections::HashSet;
+
+use futures::StreamExt;
+
+pub use crate::aggregate::{Aggregate, AggregateRoot, DryRun};
+pub use crate::codec::*;
+pub use crate::command::{AsIdempotencyKey, Command};
+pub use crate::dispatcher::Dispatcher;
+pub use crate::event::{Event, Metadata, PersistedEvent, PositionedEvent};
 pub use crate::eventstore::*;
+pub use crate::fs_snapshot::*;
+pub use crate::middleware::{Middleware, MiddlewareChain};
+pub use crate::projection::*;
+pub use crate::saga::*;
+pub use crate::shredding::*;
 pub use crate::snapshot::*;
+pub use crate::snapshot_policy::SnapshotPolicy;
+pub use crate::subscription::*;
+pub use crate::time_travel::*;
+pub use geeks_event_sourcing_derive::{Command, Event};
 
 mod aggregate;
+mod codec;
 mod command;
+mod dispatcher;
 mod event;
 mod eventstore;
+mod fs_snapshot;
+mod middleware;
+mod projection;
+mod saga;
+mod shredding;
 mod snapshot;
+mod snapshot_policy;
+mod subscription;
 pub mod testing;
+mod time_travel;
 
 pub type Version = u64;
 pub type Timestamp = i64;
+pub type Position = u64;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error<E, EE, SE> {
@@ -26,6 +53,51 @@ pub enum Error<E, EE, SE> {
 
   #[error("snapshot error: {0}")]
   SnapshotError(#[source] SE),
+
+  #[error("version conflict on '{aggregate_id}': expected {expected}, actual {actual}")]
+  VersionConflict {
+    aggregate_id: String,
+    expected: Version,
+    actual: Version,
+  },
+
+  #[error(
+    "expected '{aggregate_id}' at version {version}, found '{found_aggregate_id}' at version {found_version}"
+  )]
+  UnexpectedEvent {
+    aggregate_id: String,
+    version: Version,
+    found_aggregate_id: String,
+    found_version: Version,
+  },
+}
+
+impl<E, EE, SE> From<AppendError<EE>> for Error<E, EE, SE> {
+  fn from(e: AppendError<EE>) -> Self {
+    match e {
+      AppendError::VersionConflict {
+        aggregate_id,
+        expected,
+        actual,
+      } => Self::VersionConflict {
+        aggregate_id,
+        expected,
+        actual,
+      },
+      AppendError::UnexpectedEvent {
+        aggregate_id,
+        version,
+        found_aggregate_id,
+        found_version,
+      } => Self::UnexpectedEvent {
+        aggregate_id,
+        version,
+        found_aggregate_id,
+        found_version,
+      },
+      AppendError::EventstoreError(e) => Self::EventstoreError(e),
+    }
+  }
 }
 
 pub async fn get_unsaved_events<T, E>(
@@ -36,22 +108,49 @@ where
   T: Aggregate,
   E: Eventstore<Event = T::Event>,
 {
-  let versions = root.versions.clone();
   let mut unsaved_events = Vec::new();
-  let read_events = versions.iter().map(|(id, version)| async {
-    eventstore
-      .read(id.to_owned(), VersionSelect::From(*version + 1))
-      .await
-  });
-
-  for events in read_events {
-    let events = events.await?;
-    unsaved_events.append(&mut events.clone());
+  for id in aggregate_ids(root, eventstore, None).await? {
+    let version = root.get_version(&id).cloned().unwrap_or(0);
+    let mut events = eventstore
+      .read(id, VersionSelect::From(version + 1))
+      .await?;
+    unsaved_events.append(&mut events);
   }
 
   Ok(unsaved_events)
 }
 
+/// Ids known to the snapshot followed by ids only the eventstore knows about, which are
+/// aggregates created after the snapshot was taken. `marker` lets the eventstore skip the
+/// history the snapshot already covers.
+async fn aggregate_ids<T, E>(
+  root: &AggregateRoot<T>,
+  eventstore: &E,
+  marker: Option<&str>,
+) -> Result<Vec<String>, E::Error>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+{
+  let mut ids: Vec<String> = root.versions.keys().cloned().collect();
+  let mut seen: HashSet<String> = ids.iter().cloned().collect();
+  let stored = match marker {
+    Some(marker) => eventstore.aggregate_ids_since_snapshot(marker).await?,
+    None => eventstore.aggregate_ids().await?,
+  };
+  for id in stored {
+    if seen.insert(id.clone()) {
+      ids.push(id);
+    }
+  }
+
+  Ok(ids)
+}
+
+/// Loads the snapshot and applies the events appended after it. The caught-up root is saved
+/// back whenever events were applied or the snapshot was rebuilt, whatever `SnapshotPolicy` a
+/// `Dispatcher` uses afterwards: the policy paces saves of events it appends itself, while
+/// skipping this save would make every following load replay the same events again.
 pub async fn load_aggregate<T, E, S>(
   eventstore: E,
   snapshot: S,
@@ -61,19 +160,208 @@ where
   E: Eventstore<Event = T::Event>,
   S: Snapshot<T>,
 {
-  let mut root = snapshot.load().await.map_err(Error::SnapshotError)?;
-  let unsaved_events = get_unsaved_events(&root, &eventstore)
+  let (root, _) = load_root(&eventstore, &snapshot).await?;
+  Ok(root)
+}
+
+/// Same as `load_aggregate`, but also reports whether the snapshot had to be rebuilt.
+pub async fn load_aggregate_with_report<T, E, S>(
+  eventstore: E,
+  snapshot: S,
+) -> Result<(AggregateRoot<T>, LoadReport), Error<T::Error, E::Error, S::Error>>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+  S: Snapshot<T>,
+{
+  load_root(&eventstore, &snapshot).await
+}
+
+pub(crate) async fn load_root<T, E, S>(
+  eventstore: &E,
+  snapshot: &S,
+) -> Result<(AggregateRoot<T>, LoadReport), Error<T::Error, E::Error, S::Error>>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+  S: Snapshot<T>,
+{
+  let mut report = LoadReport::default();
+  let mut root = match snapshot.load().await {
+    Ok(x) => x,
+    Err(e) => match snapshot.invalidation(&e) {
+      Some(invalidation) => {
+        report.invalidated = Some(invalidation);
+        AggregateRoot::default()
+      }
+      None => return Err(Error::SnapshotError(e)),
+    },
+  };
+  // Taken before catching up, so everything before it is applied once the loop is done.
+  let marker = eventstore
+    .snapshot_marker()
+    .await
+    .map_err(Error::EventstoreError)?;
+  let ids = aggregate_ids(&root, eventstore, root.snapshot_marker.as_deref())
     .await
     .map_err(Error::EventstoreError)?;
+  for id in ids {
+    let version = root.get_version(&id).cloned().unwrap_or(0);
+    let mut events = eventstore.stream(id, VersionSelect::From(version + 1));
+    while let Some(event) = events.next().await {
+      let event = event.map_err(Error::EventstoreError)?;
+      root
+        .save_events(vec![event])
+        .map_err(Error::AggregateError)?;
+      report.applied_events += 1;
+    }
+  }
+  root.snapshot_marker = marker;
 
-  root
-    .save_events(unsaved_events)
-    .map_err(Error::AggregateError)?;
+  // Nothing to catch up means the stored snapshot is already current.
+  if report.applied_events > 0 || report.invalidated.is_some() {
+    snapshot
+      .save(root.clone())
+      .await
+      .map_err(Error::SnapshotError)?;
+  }
 
-  snapshot
-    .save(root.clone())
-    .await
-    .map_err(Error::SnapshotError)?;
+  Ok((root, report))
+}
 
-  Ok(root)
+#[cfg(test)]
+mod tests {
+  use geeks_git_testing::FixtureRepository;
+
+  use crate::testing::{InMemoryEventstore, InMemorySnapshot, Todo, TodoCommand};
+  use crate::{
+    get_unsaved_events, load_aggregate, load_aggregate_with_report, AggregateRoot, Eventstore,
+    FsSnapshot, Snapshot, SnapshotInvalidation,
+  };
+
+  #[tokio::test]
+  async fn should_load_aggregate_with_unsaved_events() {
+    let eventstore = InMemoryEventstore::default();
+    let snapshot = InMemorySnapshot::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+    snapshot.save(root.clone()).await.unwrap();
+
+    let events = root
+      .execute_command(TodoCommand::UpdateTodoTitle {
+        id: "todo1".to_string(),
+        title: "Eat pizza".to_string(),
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let loaded = load_aggregate(eventstore, snapshot.clone()).await.unwrap();
+    assert_eq!(loaded.get_version("todo1"), Some(&2));
+    assert_eq!(loaded.get_state("todo1").unwrap().title, "Eat pizza");
+    assert_eq!(snapshot.saved().unwrap().get_version("todo1"), Some(&2));
+  }
+
+  #[tokio::test]
+  async fn should_not_save_snapshot_without_unsaved_events() {
+    let snapshot = InMemorySnapshot::<Todo>::default();
+    let loaded = load_aggregate(InMemoryEventstore::default(), snapshot.clone())
+      .await
+      .unwrap();
+
+    assert!(loaded.versions.is_empty());
+    assert!(snapshot.saved().is_none());
+  }
+
+  #[tokio::test]
+  async fn should_load_aggregates_created_after_snapshot() {
+    let eventstore = InMemoryEventstore::default();
+    let snapshot = InMemorySnapshot::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+    snapshot.save(root.clone()).await.unwrap();
+
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo2".to_string(),
+        title: "Eat pizza".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let stale = snapshot.load().await.unwrap();
+    let unsaved = get_unsaved_events(&stale, &eventstore).await.unwrap();
+    assert_eq!(unsaved.len(), 1);
+    assert_eq!(unsaved[0].aggregate_id, "todo2");
+
+    let loaded = load_aggregate(eventstore, snapshot.clone()).await.unwrap();
+    assert_eq!(loaded.get_version("todo2"), Some(&1));
+    assert_eq!(loaded.get_state("todo2").unwrap().title, "Eat pizza");
+    assert_eq!(snapshot.saved().unwrap().get_version("todo2"), Some(&1));
+  }
+
+  #[tokio::test]
+  async fn should_rebuild_root_when_snapshot_is_invalid() {
+    let eventstore = InMemoryEventstore::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    let events = root
+      .execute_command(TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      })
+      .unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let fixture = FixtureRepository::setup();
+    let snapshot = FsSnapshot::<Todo>::new(&fixture.path);
+    std::fs::write(
+      snapshot.file_path(),
+      r#"{"format_version":1,"snapshot_version":0,"states":{},"versions":{}}"#,
+    )
+    .unwrap();
+
+    let (loaded, report) = load_aggregate_with_report(eventstore.clone(), snapshot.clone())
+      .await
+      .unwrap();
+    assert_eq!(
+      report.invalidated,
+      Some(SnapshotInvalidation::VersionMismatch {
+        stored: 0,
+        expected: 1
+      })
+    );
+    assert_eq!(report.applied_events, 1);
+    assert_eq!(loaded.get_state("todo1").unwrap().title, "Drink coffee");
+    assert_eq!(
+      snapshot.load().await.unwrap().get_version("todo1"),
+      Some(&1)
+    );
+
+    std::fs::write(snapshot.file_path(), "{ not json").unwrap();
+    let (loaded, report) = load_aggregate_with_report(eventstore, snapshot)
+      .await
+      .unwrap();
+    assert!(matches!(
+      report.invalidated,
+      Some(SnapshotInvalidation::Unreadable(_))
+    ));
+    assert_eq!(loaded.get_version("todo1"), Some(&1));
+  }
 }
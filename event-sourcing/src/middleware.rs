@@ -0,0 +1,273 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{Aggregate, Metadata, PersistedEvent};
+
+/// Layer around command execution, for concerns shared by every command such as
+/// authorization, validation or auditing. Returning an error rejects the command before
+/// anything is applied.
+pub trait Middleware<T>: Send + Sync
+where
+  T: Aggregate,
+{
+  /// Runs before the command is handled. `metadata` is the one that will be recorded on the
+  /// produced events.
+  fn before(&self, _command: &T::Command, _metadata: &mut Metadata) -> Result<(), T::Error> {
+    Ok(())
+  }
+
+  /// Runs on the produced events before they are applied to the state. Only changes to
+  /// `event` and `metadata` are kept.
+  fn after(
+    &self,
+    _command: &T::Command,
+    _events: &mut [PersistedEvent<T::Event>],
+  ) -> Result<(), T::Error> {
+    Ok(())
+  }
+}
+
+/// Middleware of a `Dispatcher`. `before` runs in the order they were added and `after` in
+/// the reverse order, so the first one added wraps all others.
+pub struct MiddlewareChain<T>
+where
+  T: Aggregate,
+{
+  layers: Vec<Arc<dyn Middleware<T>>>,
+}
+
+impl<T> Default for MiddlewareChain<T>
+where
+  T: Aggregate,
+{
+  fn default() -> Self {
+    Self { layers: Vec::new() }
+  }
+}
+
+impl<T> Clone for MiddlewareChain<T>
+where
+  T: Aggregate,
+{
+  fn clone(&self) -> Self {
+    Self {
+      layers: self.layers.clone(),
+    }
+  }
+}
+
+impl<T> fmt::Debug for MiddlewareChain<T>
+where
+  T: Aggregate,
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MiddlewareChain")
+      .field("layers", &self.layers.len())
+      .finish()
+  }
+}
+
+impl<T> MiddlewareChain<T>
+where
+  T: Aggregate,
+{
+  pub fn push<M>(&mut self, middleware: M)
+  where
+    M: Middleware<T> + 'static,
+  {
+    self.layers.push(Arc::new(middleware));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.layers.is_empty()
+  }
+
+  pub(crate) fn before(
+    &self,
+    command: &T::Command,
+    metadata: &mut Metadata,
+  ) -> Result<(), T::Error> {
+    for layer in &self.layers {
+      layer.before(command, metadata)?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn after(
+    &self,
+    command: &T::Command,
+    events: &mut [PersistedEvent<T::Event>],
+  ) -> Result<(), T::Error> {
+    for layer in self.layers.iter().rev() {
+      layer.after(command, events)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use crate::testing::{
+    create_todo, dispatcher, InMemorySnapshot, Todo, TodoCommand, TodoError, TodoEvent,
+  };
+  use crate::{
+    Command, Dispatcher, Error, Eventstore, Metadata, Middleware, PersistedEvent, VersionSelect,
+  };
+
+  struct RequireActor;
+
+  impl Middleware<Todo> for RequireActor {
+    fn before(&self, _command: &TodoCommand, metadata: &mut Metadata) -> Result<(), TodoError> {
+      match metadata.actor {
+        Some(_) => Ok(()),
+        None => Err(TodoError::Unauthorized),
+      }
+    }
+  }
+
+  struct Record(&'static str, Arc<Mutex<Vec<String>>>);
+
+  impl Middleware<Todo> for Record {
+    fn before(&self, command: &TodoCommand, _metadata: &mut Metadata) -> Result<(), TodoError> {
+      let call = format!("{} before {}", self.0, command.name());
+      self.1.lock().unwrap().push(call);
+      Ok(())
+    }
+
+    fn after(
+      &self,
+      _command: &TodoCommand,
+      events: &mut [PersistedEvent<TodoEvent>],
+    ) -> Result<(), TodoError> {
+      self.1.lock().unwrap().push(format!("{} after", self.0));
+      for event in events {
+        event
+          .metadata
+          .extra
+          .insert(self.0.to_string(), "seen".to_string());
+      }
+      Ok(())
+    }
+  }
+
+  struct Hijack;
+
+  impl Middleware<Todo> for Hijack {
+    fn after(
+      &self,
+      _command: &TodoCommand,
+      events: &mut [PersistedEvent<TodoEvent>],
+    ) -> Result<(), TodoError> {
+      for event in events {
+        event.aggregate_id = "todo_1".to_string();
+        event.version = 42;
+      }
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn should_reject_command_before_handling() {
+    let mut dispatcher = dispatcher().with_middleware(RequireActor);
+
+    let err = dispatcher
+      .dispatch(create_todo("todo_0"))
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      Error::AggregateError(TodoError::Unauthorized)
+    ));
+    assert!(dispatcher.root().get_state("todo_0").is_none());
+
+    let metadata = Metadata {
+      actor: Some("user_0".to_string()),
+      ..Metadata::default()
+    };
+    dispatcher
+      .dispatch_with_metadata(create_todo("todo_0"), metadata)
+      .await
+      .unwrap();
+    assert_eq!(dispatcher.root().get_version("todo_0"), Some(&1));
+  }
+
+  #[tokio::test]
+  async fn should_reject_replayed_idempotency_key_before_handling() {
+    let mut dispatcher = dispatcher();
+    let metadata = Metadata {
+      actor: Some("user_0".to_string()),
+      idempotency_key: Some("request_0".to_string()),
+      ..Metadata::default()
+    };
+    dispatcher
+      .dispatch_with_metadata(create_todo("todo_0"), metadata.clone())
+      .await
+      .unwrap();
+
+    let mut dispatcher = Dispatcher::new(
+      dispatcher.root().clone(),
+      dispatcher.eventstore().clone(),
+      InMemorySnapshot::default(),
+    )
+    .with_middleware(RequireActor);
+    let replayed = Metadata {
+      actor: None,
+      ..metadata.clone()
+    };
+    let err = dispatcher
+      .dispatch_with_metadata(create_todo("todo_0"), replayed)
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      Error::AggregateError(TodoError::Unauthorized)
+    ));
+
+    let events = dispatcher
+      .dispatch_with_metadata(create_todo("todo_0"), metadata)
+      .await
+      .unwrap();
+    assert_eq!(events.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn should_run_layers_around_command_and_decorate_events() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let mut dispatcher = dispatcher()
+      .with_middleware(Record("audit", calls.clone()))
+      .with_middleware(Record("validate", calls.clone()));
+
+    let events = dispatcher.dispatch(create_todo("todo_0")).await.unwrap();
+    assert_eq!(
+      *calls.lock().unwrap(),
+      vec![
+        "audit before CreateTodo",
+        "validate before CreateTodo",
+        "validate after",
+        "audit after",
+      ]
+    );
+    assert_eq!(events[0].metadata.extra.get("audit").unwrap(), "seen");
+    assert_eq!(events[0].metadata.extra.get("validate").unwrap(), "seen");
+
+    let stored = dispatcher
+      .eventstore()
+      .read("todo_0".to_string(), VersionSelect::All)
+      .await
+      .unwrap();
+    assert_eq!(stored, events);
+  }
+
+  #[tokio::test]
+  async fn should_keep_aggregate_id_and_version_of_decorated_events() {
+    let mut dispatcher = dispatcher().with_middleware(Hijack);
+
+    let events = dispatcher.dispatch(create_todo("todo_0")).await.unwrap();
+    assert_eq!(events[0].aggregate_id, "todo_0");
+    assert_eq!(events[0].version, 1);
+    assert_eq!(dispatcher.root().get_version("todo_0"), Some(&1));
+    assert!(dispatcher.root().get_state("todo_1").is_none());
+  }
+}
@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+
+use crate::{Event, Eventstore, Position, PositionedEvent};
+
+#[async_trait]
+pub trait Projection: Send + Sync {
+  type Event: Event;
+  type Error: Send + Sync;
+
+  /// Key used to store the checkpoint of this projection.
+  fn name(&self) -> &str;
+
+  async fn apply(&mut self, event: &PositionedEvent<Self::Event>) -> Result<(), Self::Error>;
+
+  /// Drops every derived state so the projection can be rebuilt from the first event.
+  async fn reset(&mut self) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+  type Error: Send + Sync;
+
+  /// Returns the last processed position, or `0` when nothing was processed yet.
+  async fn load(&self, name: &str) -> Result<Position, Self::Error>;
+
+  async fn save(&self, name: &str, position: Position) -> Result<(), Self::Error>;
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProjectionError<P, EE, CE> {
+  #[error("projection error: {0}")]
+  ProjectionError(#[source] P),
+
+  #[error("eventstore error: {0}")]
+  EventstoreError(#[source] EE),
+
+  #[error("checkpoint error: {0}")]
+  CheckpointError(#[source] CE),
+}
+
+/// Applies every event appended after the stored checkpoint and returns the new checkpoint.
+pub async fn run_projection<P, E, C>(
+  projection: &mut P,
+  eventstore: &E,
+  checkpoints: &C,
+) -> Result<Position, ProjectionError<P::Error, E::Error, C::Error>>
+where
+  P: Projection,
+  E: Eventstore<Event = P::Event>,
+  C: CheckpointStore,
+{
+  let name = projection.name().to_owned();
+  let mut position = checkpoints
+    .load(&name)
+    .await
+    .map_err(ProjectionError::CheckpointError)?;
+  let events = eventstore
+    .read_all(position)
+    .await
+    .map_err(ProjectionError::EventstoreError)?;
+
+  for event in events {
+    projection
+      .apply(&event)
+      .await
+      .map_err(ProjectionError::ProjectionError)?;
+    position = event.position;
+    checkpoints
+      .save(&name, position)
+      .await
+      .map_err(ProjectionError::CheckpointError)?;
+  }
+
+  Ok(position)
+}
+
+pub async fn rebuild_projection<P, E, C>(
+  projection: &mut P,
+  eventstore: &E,
+  checkpoints: &C,
+) -> Result<Position, ProjectionError<P::Error, E::Error, C::Error>>
+where
+  P: Projection,
+  E: Eventstore<Event = P::Event>,
+  C: CheckpointStore,
+{
+  projection
+    .reset()
+    .await
+    .map_err(ProjectionError::ProjectionError)?;
+  checkpoints
+    .save(projection.name(), 0)
+    .await
+    .map_err(ProjectionError::CheckpointError)?;
+
+  run_projection(projection, eventstore, checkpoints).await
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::convert::Infallible;
+
+  use async_trait::async_trait;
+
+  use crate::testing::{InMemoryCheckpointStore, InMemoryEventstore, Todo, TodoCommand, TodoEvent};
+  use crate::{
+    rebuild_projection, run_projection, AggregateRoot, CheckpointStore, Eventstore,
+    PositionedEvent, Projection,
+  };
+
+  #[derive(Default)]
+  struct TodoTitles {
+    titles: HashMap<String, String>,
+    applied: usize,
+  }
+
+  #[async_trait]
+  impl Projection for TodoTitles {
+    type Event = TodoEvent;
+    type Error = Infallible;
+
+    fn name(&self) -> &str {
+      "todo_titles"
+    }
+
+    async fn apply(&mut self, event: &PositionedEvent<Self::Event>) -> Result<(), Self::Error> {
+      let id = event.persisted.aggregate_id.to_owned();
+      match &event.persisted.event {
+        TodoEvent::TodoCreated { title, .. } | TodoEvent::TodoTitleUpdated { title } => {
+          self.titles.insert(id, title.to_owned());
+        }
+        TodoEvent::TodoStatusUpdated { .. } => {}
+      }
+      self.applied += 1;
+      Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+      self.titles.clear();
+      self.applied = 0;
+      Ok(())
+    }
+  }
+
+  async fn append_command(
+    root: &mut AggregateRoot<Todo>,
+    eventstore: &InMemoryEventstore<TodoEvent>,
+    command: TodoCommand,
+  ) {
+    let events = root.execute_command(command).unwrap();
+    eventstore.append(events).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn should_project_events_from_checkpoint() {
+    let eventstore = InMemoryEventstore::default();
+    let checkpoints = InMemoryCheckpointStore::default();
+    let mut projection = TodoTitles::default();
+    let mut root = AggregateRoot::default();
+
+    append_command(
+      &mut root,
+      &eventstore,
+      TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      },
+    )
+    .await;
+    let position = run_projection(&mut projection, &eventstore, &checkpoints)
+      .await
+      .unwrap();
+    assert_eq!(position, 1);
+    assert_eq!(checkpoints.load("todo_titles").await.unwrap(), 1);
+
+    append_command(
+      &mut root,
+      &eventstore,
+      TodoCommand::UpdateTodoTitle {
+        id: "todo1".to_string(),
+        title: "Eat pizza".to_string(),
+      },
+    )
+    .await;
+    let position = run_projection(&mut projection, &eventstore, &checkpoints)
+      .await
+      .unwrap();
+    assert_eq!(position, 2);
+    assert_eq!(projection.applied, 2);
+    assert_eq!(projection.titles["todo1"], "Eat pizza");
+  }
+
+  #[tokio::test]
+  async fn should_rebuild_projection_from_scratch() {
+    let eventstore = InMemoryEventstore::default();
+    let checkpoints = InMemoryCheckpointStore::default();
+    let mut projection = TodoTitles::default();
+    let mut root = AggregateRoot::default();
+
+    append_command(
+      &mut root,
+      &eventstore,
+      TodoCommand::CreateTodo {
+        id: "todo1".to_string(),
+        title: "Drink coffee".to_string(),
+        status: None,
+      },
+    )
+    .await;
+    run_projection(&mut projection, &eventstore, &checkpoints)
+      .await
+      .unwrap();
+    let position = rebuild_projection(&mut projection, &eventstore, &checkpoints)
+      .await
+      .unwrap();
+
+    assert_eq!(position, 1);
+    assert_eq!(projection.applied, 1);
+    assert_eq!(projection.titles["todo1"], "Drink coffee");
+  }
+}
@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  Aggregate, Command, Dispatcher, Error, Event, Eventstore, Metadata, Position, PositionedEvent,
+  Snapshot,
+};
+
+/// Reacts to persisted events with follow-up commands, usually for other aggregates.
+pub trait Saga: Send + Sync {
+  type Event: Event;
+  type Command: Command;
+  type State: Default + Clone + Send + Sync;
+
+  /// Key used to store the state of this saga.
+  fn name(&self) -> &str;
+
+  fn handle(
+    &self,
+    state: &mut Self::State,
+    event: &PositionedEvent<Self::Event>,
+  ) -> Vec<Self::Command>;
+}
+
+/// Persisted progress of a saga. `pending` holds commands which are not delivered yet, so they
+/// are sent again after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SagaRecord<S, C> {
+  pub state: S,
+  pub position: Position,
+  pub pending: Vec<PendingCommand<C>>,
+}
+
+/// Command waiting for delivery. The key is derived from the saga name, the position of the
+/// event and the index of the command, so a redelivery is recognized as a retry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingCommand<C> {
+  pub idempotency_key: String,
+  pub command: C,
+}
+
+impl<S, C> Default for SagaRecord<S, C>
+where
+  S: Default,
+{
+  fn default() -> Self {
+    Self {
+      state: S::default(),
+      position: 0,
+      pending: Vec::new(),
+    }
+  }
+}
+
+#[async_trait]
+pub trait SagaStore<G>: Send + Sync
+where
+  G: Saga,
+{
+  type Error: Send + Sync;
+
+  async fn load(&self, name: &str) -> Result<SagaRecord<G::State, G::Command>, Self::Error>;
+
+  async fn save(
+    &self,
+    name: &str,
+    record: SagaRecord<G::State, G::Command>,
+  ) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+pub trait CommandHandler<C>: Send
+where
+  C: Command,
+{
+  type Error: Send + Sync;
+
+  /// `metadata` carries the idempotency key of the delivery.
+  async fn handle(&mut self, command: C, metadata: Metadata) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+impl<T, E, S> CommandHandler<T::Command> for Dispatcher<T, E, S>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+  S: Snapshot<T> + Send + Sync,
+{
+  type Error = Error<T::Error, E::Error, S::Error>;
+
+  async fn handle(&mut self, command: T::Command, metadata: Metadata) -> Result<(), Self::Error> {
+    self.dispatch_with_metadata(command, metadata).await?;
+    Ok(())
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SagaError<EE, SE, HE> {
+  #[error("eventstore error: {0}")]
+  EventstoreError(#[source] EE),
+
+  #[error("saga store error: {0}")]
+  SagaStoreError(#[source] SE),
+
+  #[error("command error: {0}")]
+  CommandError(#[source] HE),
+}
+
+/// Delivers pending commands, then feeds the saga every event appended since its last position.
+/// Each command is delivered at least once, with an idempotency key so that a `Dispatcher`
+/// returns the events of an earlier delivery instead of executing it again.
+pub async fn run_saga<G, E, St, H>(
+  saga: &G,
+  eventstore: &E,
+  store: &St,
+  handler: &mut H,
+) -> Result<Position, SagaError<E::Error, St::Error, H::Error>>
+where
+  G: Saga,
+  E: Eventstore<Event = G::Event>,
+  St: SagaStore<G>,
+  H: CommandHandler<G::Command>,
+{
+  let name = saga.name().to_owned();
+  let mut record = store.load(&name).await.map_err(SagaError::SagaStoreError)?;
+  deliver_pending(&name, &mut record, store, handler).await?;
+
+  let events = eventstore
+    .read_all(record.position)
+    .await
+    .map_err(SagaError::EventstoreError)?;
+  for event in events {
+    record.pending = saga
+      .handle(&mut record.state, &event)
+      .into_iter()
+      .enumerate()
+      .map(|(step, command)| PendingCommand {
+        idempotency_key: format!("saga:{}:{}:{}", name, event.position, step),
+        command,
+      })
+      .collect();
+    record.position = event.position;
+    store
+      .save(&name, record.clone())
+      .await
+      .map_err(SagaError::SagaStoreError)?;
+    deliver_pending(&name, &mut record, store, handler).await?;
+  }
+
+  Ok(record.position)
+}
+
+async fn deliver_pending<G, St, H, EE>(
+  name: &str,
+  record: &mut SagaRecord<G::State, G::Command>,
+  store: &St,
+  handler: &mut H,
+) -> Result<(), SagaError<EE, St::Error, H::Error>>
+where
+  G: Saga,
+  St: SagaStore<G>,
+  H: CommandHandler<G::Command>,
+{
+  while let Some(pending) = record.pending.first().cloned() {
+    let metadata = Metadata {
+      idempotency_key: Some(pending.idempotency_key),
+      ..Metadata::default()
+    };
+    handler
+      .handle(pending.command, metadata)
+      .await
+      .map_err(SagaError::CommandError)?;
+    record.pending.remove(0);
+    store
+      .save(name, record.clone())
+      .await
+      .map_err(SagaError::SagaStoreError)?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use async_trait::async_trait;
+
+  use crate::testing::{
+    create_todo, dispatcher, InMemoryEventstore, InMemorySagaStore, InMemorySnapshot, Todo,
+    TodoCommand, TodoEvent,
+  };
+  use crate::{
+    run_saga, CommandHandler, Dispatcher, Metadata, PendingCommand, PositionedEvent, Saga,
+    SagaError, SagaStore,
+  };
+
+  struct ReminderSaga;
+
+  impl Saga for ReminderSaga {
+    type Event = TodoEvent;
+    type Command = TodoCommand;
+    type State = u32;
+
+    fn name(&self) -> &str {
+      "reminders"
+    }
+
+    fn handle(&self, state: &mut u32, event: &PositionedEvent<TodoEvent>) -> Vec<TodoCommand> {
+      match &event.persisted.event {
+        TodoEvent::TodoCreated { id, title, .. } if !id.starts_with("reminder_") => {
+          *state += 1;
+          vec![TodoCommand::CreateTodo {
+            id: format!("reminder_{}", id),
+            title: format!("Reminder: {}", title),
+            status: None,
+          }]
+        }
+        _ => Vec::new(),
+      }
+    }
+  }
+
+  type TodoDispatcher = Dispatcher<Todo, InMemoryEventstore<TodoEvent>, InMemorySnapshot<Todo>>;
+
+  struct CrashingHandler<'a> {
+    dispatcher: &'a mut TodoDispatcher,
+    crash: bool,
+    crash_after_dispatch: bool,
+  }
+
+  #[async_trait]
+  impl<'a> CommandHandler<TodoCommand> for CrashingHandler<'a> {
+    type Error = &'static str;
+
+    async fn handle(
+      &mut self,
+      command: TodoCommand,
+      metadata: Metadata,
+    ) -> Result<(), Self::Error> {
+      if self.crash {
+        return Err("crashed");
+      }
+      self
+        .dispatcher
+        .dispatch_with_metadata(command, metadata)
+        .await
+        .map_err(|_| "failed")?;
+      if self.crash_after_dispatch {
+        return Err("crashed");
+      }
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn should_dispatch_follow_up_commands() {
+    let store = InMemorySagaStore::default();
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+
+    let position = run_saga(&ReminderSaga, &eventstore, &store, &mut dispatcher)
+      .await
+      .unwrap();
+    assert_eq!(position, 1);
+
+    let reminder = dispatcher.root().get_state("reminder_todo1").unwrap();
+    assert_eq!(reminder.title, "Reminder: Drink coffee");
+
+    let position = run_saga(&ReminderSaga, &eventstore, &store, &mut dispatcher)
+      .await
+      .unwrap();
+    assert_eq!(position, 2);
+    let record = SagaStore::<ReminderSaga>::load(&store, "reminders")
+      .await
+      .unwrap();
+    assert_eq!(record.state, 1);
+    assert!(record.pending.is_empty());
+  }
+
+  #[tokio::test]
+  async fn should_resume_pending_commands_after_crash() {
+    let store = InMemorySagaStore::default();
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+
+    let mut handler = CrashingHandler {
+      dispatcher: &mut dispatcher,
+      crash: true,
+      crash_after_dispatch: false,
+    };
+    let err = run_saga(&ReminderSaga, &eventstore, &store, &mut handler)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, SagaError::CommandError("crashed")));
+
+    let record = SagaStore::<ReminderSaga>::load(&store, "reminders")
+      .await
+      .unwrap();
+    assert_eq!(record.position, 1);
+    assert_eq!(
+      record.pending,
+      vec![PendingCommand {
+        idempotency_key: "saga:reminders:1:0".to_string(),
+        command: create_reminder("todo1"),
+      }]
+    );
+
+    handler.crash = false;
+    run_saga(&ReminderSaga, &eventstore, &store, &mut handler)
+      .await
+      .unwrap();
+    assert!(dispatcher.root().get_state("reminder_todo1").is_some());
+  }
+
+  #[tokio::test]
+  async fn should_dedupe_redelivered_commands() {
+    let store = InMemorySagaStore::default();
+    let mut dispatcher = dispatcher();
+    let eventstore = dispatcher.eventstore().clone();
+    dispatcher.dispatch(create_todo("todo1")).await.unwrap();
+
+    // The command is executed, but the saga crashes before recording the delivery.
+    let mut handler = CrashingHandler {
+      dispatcher: &mut dispatcher,
+      crash: false,
+      crash_after_dispatch: true,
+    };
+    run_saga(&ReminderSaga, &eventstore, &store, &mut handler)
+      .await
+      .unwrap_err();
+
+    handler.crash_after_dispatch = false;
+    let position = run_saga(&ReminderSaga, &eventstore, &store, &mut handler)
+      .await
+      .unwrap();
+    assert_eq!(position, 2);
+    let record = SagaStore::<ReminderSaga>::load(&store, "reminders")
+      .await
+      .unwrap();
+    assert!(record.pending.is_empty());
+    assert_eq!(dispatcher.root().get_version("reminder_todo1"), Some(&1));
+  }
+
+  fn create_reminder(id: &str) -> TodoCommand {
+    TodoCommand::CreateTodo {
+      id: format!("reminder_{}", id),
+      title: "Reminder: Drink coffee".to_string(),
+      status: None,
+    }
+  }
+}
@@ -0,0 +1,401 @@
+use std::fmt::Debug;
+use std::fs::{create_dir_all, hard_link, read, remove_file, write};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, OsRng, Payload};
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305, KeyInit, Nonce};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Replaces personal data fields whose key was deleted.
+pub const REDACTED: &str = "[redacted]";
+
+/// Types a personal data field can have, which are the ones deserializing from `REDACTED`.
+/// Deriving `Event` rejects `#[event(personal_data)]` fields of any other type:
+///
+/// ```compile_fail
+/// use geeks_event_sourcing::Event;
+///
+/// #[derive(Clone, serde::Serialize, serde::Deserialize, Event)]
+/// #[serde(tag = "name")]
+/// enum UserEvent {
+///   BirthYearRecorded {
+///     #[event(personal_data)]
+///     birth_year: u32,
+///   },
+/// }
+/// ```
+pub trait PersonalData {}
+
+impl PersonalData for String {}
+
+impl PersonalData for Option<String> {}
+
+const ENCRYPTED_KEY: &str = "$encrypted";
+
+pub type EncryptionKey = [u8; 32];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ShreddingError {
+  #[error("key store error: {0}")]
+  KeyStoreError(#[source] io::Error),
+
+  #[error("personal data fields require a key store")]
+  MissingKeyStore,
+
+  #[error("invalid encrypted field: {0}")]
+  InvalidField(String),
+}
+
+/// Holds one encryption key per data subject. It must be stored apart from the events, so
+/// deleting a key makes the personal data of its subject unreadable for good.
+pub trait KeyStore: Debug + Send + Sync {
+  fn key(&self, subject: &str) -> Result<Option<EncryptionKey>, ShreddingError>;
+
+  fn get_or_create_key(&self, subject: &str) -> Result<EncryptionKey, ShreddingError>;
+
+  fn delete_key(&self, subject: &str) -> Result<(), ShreddingError>;
+}
+
+pub fn generate_key() -> EncryptionKey {
+  ChaCha20Poly1305::generate_key(&mut OsRng).into()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+  subject: String,
+  /// Tells a key created after the subject was forgotten from the key the data was encrypted
+  /// with. Missing on envelopes written before it was added.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  key_id: Option<String>,
+  nonce: String,
+  data: String,
+}
+
+/// Identifies `key` without revealing it, as the tag of an empty message under a fixed nonce.
+fn key_id(key: &EncryptionKey) -> String {
+  let tag = ChaCha20Poly1305::new(key.into())
+    .encrypt(
+      &Nonce::default(),
+      Payload {
+        msg: &[],
+        aad: b"key id",
+      },
+    )
+    .expect("encrypt empty message");
+  STANDARD.encode(&tag[..8])
+}
+
+/// Replaces `value` with an envelope holding it encrypted. The subject is authenticated, so an
+/// envelope cannot be passed off as data of another subject.
+pub(crate) fn encrypt_field(
+  key: &EncryptionKey,
+  subject: &str,
+  value: &Value,
+) -> Result<Value, ShreddingError> {
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+  let plaintext = value.to_string();
+  let data = ChaCha20Poly1305::new(key.into())
+    .encrypt(
+      &nonce,
+      Payload {
+        msg: plaintext.as_bytes(),
+        aad: subject.as_bytes(),
+      },
+    )
+    .map_err(|_| ShreddingError::InvalidField("failed to encrypt".to_string()))?;
+
+  let envelope = Envelope {
+    subject: subject.to_string(),
+    key_id: Some(key_id(key)),
+    nonce: STANDARD.encode(nonce),
+    data: STANDARD.encode(data),
+  };
+  Ok(json!({ ENCRYPTED_KEY: envelope }))
+}
+
+/// Decrypts `value` in place when it is an envelope, or replaces it with `REDACTED` when the
+/// key of its subject was deleted, even if a new key was created for the subject since.
+pub(crate) fn decrypt_field(
+  key_store: Option<&dyn KeyStore>,
+  value: &mut Value,
+) -> Result<(), ShreddingError> {
+  let envelope = match value.get(ENCRYPTED_KEY) {
+    Some(x) => Envelope::deserialize(x).map_err(|e| ShreddingError::InvalidField(e.to_string()))?,
+    None => return Ok(()),
+  };
+  let key = match key_store
+    .ok_or(ShreddingError::MissingKeyStore)?
+    .key(&envelope.subject)?
+    .filter(|key| envelope.key_id.as_ref().is_none_or(|id| *id == key_id(key)))
+  {
+    Some(x) => x,
+    None => {
+      *value = Value::String(REDACTED.to_string());
+      return Ok(());
+    }
+  };
+
+  let invalid = |e: &dyn ToString| ShreddingError::InvalidField(e.to_string());
+  let nonce = STANDARD.decode(&envelope.nonce).map_err(|e| invalid(&e))?;
+  if nonce.len() != 12 {
+    return Err(invalid(&"invalid nonce"));
+  }
+  let data = STANDARD.decode(&envelope.data).map_err(|e| invalid(&e))?;
+  let plaintext = ChaCha20Poly1305::new((&key).into())
+    .decrypt(
+      Nonce::from_slice(&nonce),
+      Payload {
+        msg: &data,
+        aad: envelope.subject.as_bytes(),
+      },
+    )
+    .map_err(|_| invalid(&"failed to decrypt"))?;
+  *value = serde_json::from_slice(&plaintext).map_err(|e| invalid(&e))?;
+
+  Ok(())
+}
+
+/// Keeps one key file per subject in `dir`, which must not be part of the eventstore (e.g.
+/// outside of the git repository).
+#[derive(Debug, Clone)]
+pub struct FsKeyStore {
+  dir: PathBuf,
+}
+
+impl FsKeyStore {
+  pub fn new(dir: &Path) -> Self {
+    Self {
+      dir: dir.to_path_buf(),
+    }
+  }
+
+  fn key_path(&self, subject: &str) -> PathBuf {
+    self
+      .dir
+      .join(format!("{}.key", URL_SAFE_NO_PAD.encode(subject)))
+  }
+}
+
+impl KeyStore for FsKeyStore {
+  fn key(&self, subject: &str) -> Result<Option<EncryptionKey>, ShreddingError> {
+    let raw = match read(self.key_path(subject)) {
+      Ok(x) => x,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(ShreddingError::KeyStoreError(e)),
+    };
+
+    let key = raw.try_into().map_err(|_| {
+      ShreddingError::KeyStoreError(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid key for '{}'", subject),
+      ))
+    })?;
+    Ok(Some(key))
+  }
+
+  fn get_or_create_key(&self, subject: &str) -> Result<EncryptionKey, ShreddingError> {
+    if let Some(key) = self.key(subject)? {
+      return Ok(key);
+    }
+
+    create_dir_all(&self.dir).map_err(ShreddingError::KeyStoreError)?;
+    let key = generate_key();
+    let key_path = self.key_path(subject);
+    let tmp_path = key_path.with_extension(format!("{:x}.tmp", OsRng.next_u64()));
+    write(&tmp_path, key).map_err(ShreddingError::KeyStoreError)?;
+    // Linking fails when another writer created the key first, in which case theirs is kept.
+    let linked = hard_link(&tmp_path, &key_path);
+    remove_file(&tmp_path).map_err(ShreddingError::KeyStoreError)?;
+    match linked {
+      Ok(()) => Ok(key),
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+        self.key(subject)?.ok_or(ShreddingError::KeyStoreError(e))
+      }
+      Err(e) => Err(ShreddingError::KeyStoreError(e)),
+    }
+  }
+
+  fn delete_key(&self, subject: &str) -> Result<(), ShreddingError> {
+    match remove_file(self.key_path(subject)) {
+      Ok(()) => Ok(()),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(ShreddingError::KeyStoreError(e)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use geeks_git_testing::FixtureRepository;
+
+  use super::*;
+  use crate::testing::InMemoryKeyStore;
+  use crate::{CodecError, Event, EventCodec, Metadata, PersistedEvent};
+
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Event)]
+  #[serde(tag = "name")]
+  enum UserEvent {
+    UserRegistered {
+      #[event(personal_data)]
+      email: String,
+      plan: String,
+    },
+    UserInvited {
+      #[event(data_subject)]
+      invitee: String,
+      #[event(personal_data)]
+      #[serde(rename = "invitee_email")]
+      email: String,
+    },
+    NicknameChanged {
+      #[event(personal_data)]
+      nickname: Option<String>,
+    },
+  }
+
+  fn registered() -> PersistedEvent<UserEvent> {
+    PersistedEvent {
+      aggregate_id: "user1".to_string(),
+      version: 1,
+      event: UserEvent::UserRegistered {
+        email: "user1@example.com".to_string(),
+        plan: "free".to_string(),
+      },
+      metadata: Metadata::default(),
+    }
+  }
+
+  #[test]
+  fn should_derive_personal_data_fields() {
+    let invited = UserEvent::UserInvited {
+      invitee: "user2".to_string(),
+      email: "user2@example.com".to_string(),
+    };
+    assert_eq!(registered().event.personal_data_fields(), &["email"]);
+    assert_eq!(registered().event.data_subject(), None);
+    assert_eq!(invited.personal_data_fields(), &["invitee_email"]);
+    assert_eq!(invited.data_subject(), Some("user2"));
+  }
+
+  #[test]
+  fn should_redact_fields_after_key_is_deleted() {
+    let key_store = InMemoryKeyStore::default();
+    let codec = EventCodec::new().key_store(Arc::new(key_store.clone()));
+
+    let raw = codec.encode(&registered()).unwrap();
+    assert!(!raw.contains("user1@example.com"));
+    assert!(raw.contains("free"));
+    assert_eq!(codec.decode(&raw).unwrap(), registered());
+
+    key_store.delete_key("user1").unwrap();
+    let decoded = codec.decode(&raw).unwrap();
+    assert_eq!(decoded.version, 1);
+    assert_eq!(
+      decoded.event,
+      UserEvent::UserRegistered {
+        email: REDACTED.to_string(),
+        plan: "free".to_string(),
+      }
+    );
+  }
+
+  #[test]
+  fn should_redact_fields_after_key_is_deleted_and_created_again() {
+    let key_store = InMemoryKeyStore::default();
+    let codec = EventCodec::new().key_store(Arc::new(key_store.clone()));
+    let raw = codec.encode(&registered()).unwrap();
+
+    key_store.delete_key("user1").unwrap();
+    let registered_again = PersistedEvent {
+      version: 2,
+      event: UserEvent::UserRegistered {
+        email: "user1@example.org".to_string(),
+        plan: "pro".to_string(),
+      },
+      ..registered()
+    };
+    let raw_again = codec.encode(&registered_again).unwrap();
+
+    let decoded = codec.decode(&raw).unwrap();
+    assert_eq!(
+      decoded.event,
+      UserEvent::UserRegistered {
+        email: REDACTED.to_string(),
+        plan: "free".to_string(),
+      }
+    );
+    assert_eq!(codec.decode(&raw_again).unwrap(), registered_again);
+  }
+
+  #[test]
+  fn should_redact_optional_fields_after_key_is_deleted() {
+    let key_store = InMemoryKeyStore::default();
+    let codec = EventCodec::new().key_store(Arc::new(key_store.clone()));
+    let events = [Some("Bob".to_string()), None].map(|nickname| PersistedEvent {
+      event: UserEvent::NicknameChanged { nickname },
+      ..registered()
+    });
+    let raw = events.each_ref().map(|x| codec.encode(x).unwrap());
+
+    key_store.delete_key("user1").unwrap();
+    for raw in raw {
+      let decoded = codec.decode(&raw).unwrap();
+      assert_eq!(
+        decoded.event,
+        UserEvent::NicknameChanged {
+          nickname: Some(REDACTED.to_string())
+        }
+      );
+    }
+  }
+
+  #[test]
+  fn should_encrypt_with_key_of_data_subject() {
+    let key_store = InMemoryKeyStore::default();
+    let codec = EventCodec::new().key_store(Arc::new(key_store.clone()));
+    let invited = PersistedEvent {
+      event: UserEvent::UserInvited {
+        invitee: "user2".to_string(),
+        email: "user2@example.com".to_string(),
+      },
+      ..registered()
+    };
+
+    let raw = codec.encode(&invited).unwrap();
+    assert!(key_store.key("user1").unwrap().is_none());
+    key_store.delete_key("user2").unwrap();
+    let decoded = codec.decode(&raw).unwrap();
+    assert!(matches!(decoded.event, UserEvent::UserInvited { email, .. } if email == REDACTED));
+  }
+
+  #[test]
+  fn should_require_key_store_for_personal_data() {
+    let err = EventCodec::new().encode(&registered()).unwrap_err();
+    assert!(matches!(
+      err,
+      CodecError::ShreddingError(ShreddingError::MissingKeyStore)
+    ));
+  }
+
+  #[test]
+  fn should_keep_keys_in_files() {
+    let fixture = FixtureRepository::setup();
+    let key_store = FsKeyStore::new(&fixture.path.join("keys"));
+    assert!(key_store.key("user/1").unwrap().is_none());
+
+    let key = key_store.get_or_create_key("user/1").unwrap();
+    assert_eq!(key_store.get_or_create_key("user/1").unwrap(), key);
+    assert_eq!(key_store.key("user/1").unwrap(), Some(key));
+
+    key_store.delete_key("user/1").unwrap();
+    assert!(key_store.key("user/1").unwrap().is_none());
+    key_store.delete_key("user/1").unwrap();
+  }
+}
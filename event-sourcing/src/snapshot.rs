@@ -2,6 +2,21 @@ use async_trait::async_trait;
 
 use crate::{Aggregate, AggregateRoot};
 
+/// Why a stored snapshot was discarded instead of loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotInvalidation {
+  VersionMismatch { stored: u32, expected: u32 },
+  Unreadable(String),
+}
+
+/// Outcome of loading a root from a snapshot and the eventstore.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+  /// Set when the snapshot was discarded and the root was rebuilt from every event.
+  pub invalidated: Option<SnapshotInvalidation>,
+  pub applied_events: u64,
+}
+
 #[async_trait]
 pub trait Snapshot<T>
 where
@@ -9,9 +24,17 @@ where
 {
   type Error: Send + Sync;
 
+  /// Implementations persisting state should store `T::SNAPSHOT_VERSION` along with it and
+  /// fail when it does not match on load.
   async fn load(&self) -> Result<AggregateRoot<T>, Self::Error>;
 
   async fn save(&self, root: AggregateRoot<T>) -> Result<(), Self::Error>;
+
+  /// Classifies a `load` error as a stale or unreadable snapshot, which is discarded and
+  /// rebuilt rather than reported as an error.
+  fn invalidation(&self, _error: &Self::Error) -> Option<SnapshotInvalidation> {
+    None
+  }
 }
 
 #[cfg(test)]
@@ -0,0 +1,60 @@
+use crate::Timestamp;
+
+/// Decides when a `Dispatcher` saves its snapshot after events were applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotPolicy {
+  /// Saves once at least `n` events were applied since the last snapshot.
+  EveryEvents(u64),
+  /// Saves on the first applied event at least `n` seconds after the last snapshot.
+  EverySeconds(i64),
+  /// Saves only when `Dispatcher::shutdown` is called.
+  OnShutdown,
+  Never,
+}
+
+impl Default for SnapshotPolicy {
+  fn default() -> Self {
+    Self::EveryEvents(1)
+  }
+}
+
+impl SnapshotPolicy {
+  pub fn should_snapshot(
+    &self,
+    unsaved_events: u64,
+    last_snapshot_at: Timestamp,
+    now: Timestamp,
+  ) -> bool {
+    if unsaved_events == 0 {
+      return false;
+    }
+
+    match self {
+      Self::EveryEvents(n) => unsaved_events >= *n,
+      Self::EverySeconds(n) => now - last_snapshot_at >= *n,
+      Self::OnShutdown | Self::Never => false,
+    }
+  }
+
+  pub fn should_snapshot_on_shutdown(&self, unsaved_events: u64) -> bool {
+    unsaved_events > 0 && *self != Self::Never
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::SnapshotPolicy;
+
+  #[test]
+  fn should_evaluate_policies() {
+    assert!(!SnapshotPolicy::EveryEvents(3).should_snapshot(2, 0, 0));
+    assert!(SnapshotPolicy::EveryEvents(3).should_snapshot(3, 0, 0));
+    assert!(!SnapshotPolicy::EverySeconds(60).should_snapshot(1, 100, 159));
+    assert!(SnapshotPolicy::EverySeconds(60).should_snapshot(1, 100, 160));
+    assert!(!SnapshotPolicy::EverySeconds(60).should_snapshot(0, 100, 500));
+    assert!(!SnapshotPolicy::OnShutdown.should_snapshot(100, 0, 500));
+    assert!(SnapshotPolicy::OnShutdown.should_snapshot_on_shutdown(1));
+    assert!(!SnapshotPolicy::OnShutdown.should_snapshot_on_shutdown(0));
+    assert!(!SnapshotPolicy::Never.should_snapshot_on_shutdown(100));
+  }
+}
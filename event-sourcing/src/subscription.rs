@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+
+use futures::stream::{self, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{Event, Eventstore, PersistedEvent, Position, PositionedEvent};
+
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Broadcasts events to in-process subscribers after they were appended.
+#[derive(Debug, Clone)]
+pub struct EventBus<T>
+where
+  T: Event,
+{
+  sender: broadcast::Sender<PositionedEvent<T>>,
+}
+
+impl<T> Default for EventBus<T>
+where
+  T: Event,
+{
+  fn default() -> Self {
+    Self::new(EVENT_BUS_CAPACITY)
+  }
+}
+
+impl<T> EventBus<T>
+where
+  T: Event,
+{
+  pub fn new(capacity: usize) -> Self {
+    let (sender, _) = broadcast::channel(capacity);
+    Self { sender }
+  }
+
+  pub fn has_subscribers(&self) -> bool {
+    self.sender.receiver_count() > 0
+  }
+
+  pub fn publish(&self, events: Vec<PositionedEvent<T>>) {
+    for event in events {
+      // Sending only fails when nobody listens, which is fine.
+      let _ = self.sender.send(event);
+    }
+  }
+
+  pub fn receiver(&self) -> broadcast::Receiver<PositionedEvent<T>> {
+    self.sender.subscribe()
+  }
+}
+
+pub trait EventPublisher: Eventstore {
+  fn event_bus(&self) -> &EventBus<Self::Event>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionFilter {
+  pub aggregate_id: Option<String>,
+  pub event_name: Option<String>,
+}
+
+impl SubscriptionFilter {
+  #[must_use]
+  pub fn aggregate_id<S: Into<String>>(self, aggregate_id: S) -> Self {
+    Self {
+      aggregate_id: Some(aggregate_id.into()),
+      ..self
+    }
+  }
+
+  #[must_use]
+  pub fn event_name<S: Into<String>>(self, event_name: S) -> Self {
+    Self {
+      event_name: Some(event_name.into()),
+      ..self
+    }
+  }
+
+  pub fn matches<T: Event>(&self, persisted: &PersistedEvent<T>) -> bool {
+    let id_matches = match &self.aggregate_id {
+      Some(id) => id == &persisted.aggregate_id,
+      None => true,
+    };
+    let name_matches = match &self.event_name {
+      Some(name) => name == persisted.event.name(),
+      None => true,
+    };
+
+    id_matches && name_matches
+  }
+}
+
+struct SubscriptionState<'a, E>
+where
+  E: EventPublisher,
+{
+  eventstore: &'a E,
+  receiver: broadcast::Receiver<PositionedEvent<E::Event>>,
+  filter: SubscriptionFilter,
+  last: Position,
+  pending: VecDeque<PositionedEvent<E::Event>>,
+  needs_catch_up: bool,
+}
+
+/// Streams events appended after `after`: stored events are read first, then live events from
+/// the event bus. Missed live events (lagging or other writers) are read again from the store.
+pub fn subscribe<E>(
+  eventstore: &E,
+  filter: SubscriptionFilter,
+  after: Position,
+) -> impl Stream<Item = Result<PositionedEvent<E::Event>, E::Error>> + '_
+where
+  E: EventPublisher,
+{
+  let state = SubscriptionState {
+    eventstore,
+    // Listen before catching up, so nothing appended in between is lost.
+    receiver: eventstore.event_bus().receiver(),
+    filter,
+    last: after,
+    pending: VecDeque::new(),
+    needs_catch_up: true,
+  };
+
+  stream::unfold(state, |mut state| async move {
+    loop {
+      if let Some(event) = state.pending.pop_front() {
+        if event.position <= state.last {
+          continue;
+        }
+        state.last = event.position;
+        if state.filter.matches(&event.persisted) {
+          return Some((Ok(event), state));
+        }
+        continue;
+      }
+
+      if state.needs_catch_up {
+        match state.eventstore.read_all(state.last).await {
+          Ok(events) => {
+            state.needs_catch_up = false;
+            state.pending.extend(events);
+            continue;
+          }
+          Err(e) => return Some((Err(e), state)),
+        }
+      }
+
+      match state.receiver.recv().await {
+        Ok(event) if event.position <= state.last => {}
+        Ok(event) if event.position == state.last + 1 => state.pending.push_back(event),
+        Ok(_) | Err(RecvError::Lagged(_)) => state.needs_catch_up = true,
+        Err(RecvError::Closed) => return None,
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::StreamExt;
+
+  use crate::testing::{create_todo, InMemoryEventstore, Todo, TodoCommand, TodoEvent};
+  use crate::{subscribe, AggregateRoot, Eventstore, SubscriptionFilter};
+
+  #[tokio::test]
+  async fn should_catch_up_and_then_receive_live_events() {
+    let eventstore = InMemoryEventstore::<TodoEvent>::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    for id in ["todo1", "todo2"] {
+      let events = root.execute_command(create_todo(id)).unwrap();
+      eventstore.append(events).await.unwrap();
+    }
+
+    let subscription = subscribe(&eventstore, SubscriptionFilter::default(), 1);
+    futures::pin_mut!(subscription);
+
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 2);
+    assert_eq!(event.persisted.aggregate_id, "todo2");
+
+    let events = root.execute_command(create_todo("todo3")).unwrap();
+    eventstore.append(events).await.unwrap();
+
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 3);
+    assert_eq!(event.persisted.aggregate_id, "todo3");
+  }
+
+  #[tokio::test]
+  async fn should_filter_events() {
+    let eventstore = InMemoryEventstore::<TodoEvent>::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    let filter = SubscriptionFilter::default()
+      .aggregate_id("todo1")
+      .event_name("TodoTitleUpdated");
+    let subscription = subscribe(&eventstore, filter, 0);
+    futures::pin_mut!(subscription);
+
+    let commands = vec![
+      create_todo("todo1"),
+      create_todo("todo2"),
+      TodoCommand::UpdateTodoTitle {
+        id: "todo2".to_string(),
+        title: "Eat rice".to_string(),
+      },
+      TodoCommand::UpdateTodoTitle {
+        id: "todo1".to_string(),
+        title: "Eat pizza".to_string(),
+      },
+    ];
+    for command in commands {
+      let events = root.execute_command(command).unwrap();
+      eventstore.append(events).await.unwrap();
+    }
+
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(event.position, 4);
+    assert_eq!(
+      event.persisted.event,
+      TodoEvent::TodoTitleUpdated {
+        title: "Eat pizza".to_string()
+      }
+    );
+  }
+}
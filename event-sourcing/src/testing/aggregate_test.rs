@@ -0,0 +1,254 @@
+use std::fmt::{Debug, Write};
+
+use crate::{Aggregate, AggregateRoot, Command, PersistedEvent, Version};
+
+/// Given/When/Then harness for `Aggregate` implementations.
+///
+/// ```
+/// use geeks_event_sourcing::testing::{AggregateTest, Todo, TodoCommand, TodoEvent, TodoStatus};
+///
+/// AggregateTest::<Todo>::default()
+///   .given(
+///     "todo_0",
+///     vec![TodoEvent::TodoCreated {
+///       id: "todo_0".to_string(),
+///       title: "Eat rice".to_string(),
+///       status: TodoStatus::Todo,
+///     }],
+///   )
+///   .when(TodoCommand::UpdateTodoStatus {
+///     id: "todo_0".to_string(),
+///     status: TodoStatus::Done,
+///   })
+///   .then_expect(vec![TodoEvent::TodoStatusUpdated {
+///     status: TodoStatus::Done,
+///   }]);
+/// ```
+pub struct AggregateTest<T>
+where
+  T: Aggregate,
+{
+  root: AggregateRoot<T>,
+}
+
+impl<T> Default for AggregateTest<T>
+where
+  T: Aggregate,
+{
+  fn default() -> Self {
+    Self {
+      root: AggregateRoot::default(),
+    }
+  }
+}
+
+impl<T> AggregateTest<T>
+where
+  T: Aggregate + Debug,
+  T::Event: Debug + PartialEq,
+  T::Error: Debug + PartialEq,
+{
+  /// Applies past events to the aggregate with the given id. Call it once per aggregate
+  /// to set up several of them.
+  #[must_use]
+  pub fn given<K: Into<String>>(mut self, id: K, events: Vec<T::Event>) -> Self {
+    let id = id.into();
+    let mut version: Version = self.root.get_version(&id).cloned().unwrap_or(0);
+    let persisted = events
+      .into_iter()
+      .map(|event| {
+        version += 1;
+        PersistedEvent {
+          aggregate_id: id.to_owned(),
+          version,
+          event,
+          metadata: Default::default(),
+        }
+      })
+      .collect();
+    if let Err(e) = self.root.save_events(persisted) {
+      panic!("given events for \"{}\" could not be applied: {:?}", id, e);
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn when(mut self, command: T::Command) -> AggregateTestResult<T> {
+    let aggregate_id = command.aggregate_id().to_owned();
+    let result = self.root.execute_command(command);
+    AggregateTestResult {
+      aggregate_id,
+      root: self.root,
+      result,
+    }
+  }
+}
+
+pub struct AggregateTestResult<T>
+where
+  T: Aggregate,
+{
+  aggregate_id: String,
+  root: AggregateRoot<T>,
+  result: Result<Vec<PersistedEvent<T::Event>>, T::Error>,
+}
+
+impl<T> AggregateTestResult<T>
+where
+  T: Aggregate + Debug,
+  T::Event: Debug + PartialEq,
+  T::Error: Debug + PartialEq,
+{
+  /// Asserts the command produced exactly these events, in order.
+  pub fn then_expect(self, expected: Vec<T::Event>) -> AggregateRoot<T> {
+    let actual = match &self.result {
+      Ok(events) => events.iter().map(|x| x.event.clone()).collect::<Vec<_>>(),
+      Err(e) => panic!(
+        "expected events for \"{}\" but the command failed: {:?}",
+        self.aggregate_id, e
+      ),
+    };
+    if actual != expected {
+      panic!(
+        "unexpected events for \"{}\" (- expected, + actual):\n{}",
+        self.aggregate_id,
+        diff(&format!("{:#?}", expected), &format!("{:#?}", actual))
+      );
+    }
+    self.root
+  }
+
+  /// Asserts the command was rejected with this error.
+  pub fn then_error(self, expected: T::Error) {
+    match self.result {
+      Ok(events) => panic!(
+        "expected error {:?} for \"{}\" but the command produced: {:#?}",
+        expected,
+        self.aggregate_id,
+        events.into_iter().map(|x| x.event).collect::<Vec<_>>()
+      ),
+      Err(actual) if actual != expected => panic!(
+        "unexpected error for \"{}\" (- expected, + actual):\n{}",
+        self.aggregate_id,
+        diff(&format!("{:#?}", expected), &format!("{:#?}", actual))
+      ),
+      Err(_) => {}
+    }
+  }
+}
+
+/// Line diff of two pretty-printed values, based on their longest common subsequence.
+fn diff(expected: &str, actual: &str) -> String {
+  let expected = expected.lines().collect::<Vec<_>>();
+  let actual = actual.lines().collect::<Vec<_>>();
+  let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+  for i in (0..expected.len()).rev() {
+    for j in (0..actual.len()).rev() {
+      lcs[i][j] = if expected[i] == actual[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+
+  let mut out = String::new();
+  let (mut i, mut j) = (0, 0);
+  while i < expected.len() || j < actual.len() {
+    if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+      let _ = writeln!(out, "  {}", expected[i]);
+      i += 1;
+      j += 1;
+    } else if j < actual.len() && (i == expected.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+      let _ = writeln!(out, "+ {}", actual[j]);
+      j += 1;
+    } else {
+      let _ = writeln!(out, "- {}", expected[i]);
+      i += 1;
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use crate::testing::{AggregateTest, Todo, TodoCommand, TodoError, TodoEvent, TodoStatus};
+
+  use super::diff;
+
+  fn created(id: &str, title: &str) -> TodoEvent {
+    TodoEvent::TodoCreated {
+      id: id.to_string(),
+      title: title.to_string(),
+      status: TodoStatus::Todo,
+    }
+  }
+
+  #[test]
+  fn should_expect_events_across_many_aggregates() {
+    let root = AggregateTest::<Todo>::default()
+      .given("todo_0", vec![created("todo_0", "Eat rice")])
+      .given("todo_1", vec![created("todo_1", "Drink soda")])
+      .when(TodoCommand::UpdateTodo {
+        id: "todo_1".to_string(),
+        title: Some("Drink water".to_string()),
+        status: Some(TodoStatus::Done),
+        request_id: None,
+      })
+      .then_expect(vec![
+        TodoEvent::TodoTitleUpdated {
+          title: "Drink water".to_string(),
+        },
+        TodoEvent::TodoStatusUpdated {
+          status: TodoStatus::Done,
+        },
+      ]);
+
+    assert_eq!(root.get_version("todo_0"), Some(&1));
+    assert_eq!(root.get_version("todo_1"), Some(&3));
+  }
+
+  #[test]
+  fn should_expect_error() {
+    AggregateTest::<Todo>::default()
+      .given("todo_0", vec![created("todo_0", "Eat rice")])
+      .when(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .then_error(TodoError::AlreadyExists);
+  }
+
+  #[test]
+  #[should_panic(expected = "unexpected events for \"todo_0\"")]
+  fn should_panic_on_mismatched_events() {
+    AggregateTest::<Todo>::default()
+      .when(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .then_expect(vec![created("todo_0", "Eat pizza")]);
+  }
+
+  #[test]
+  #[should_panic(expected = "but the command produced")]
+  fn should_panic_when_error_expected_but_events_produced() {
+    AggregateTest::<Todo>::default()
+      .when(TodoCommand::CreateTodo {
+        id: "todo_0".to_string(),
+        title: "Eat rice".to_string(),
+        status: None,
+      })
+      .then_error(TodoError::AlreadyExists);
+  }
+
+  #[test]
+  fn should_mark_changed_lines_in_diff() {
+    assert_eq!(
+      diff("[\n  a,\n  b,\n]", "[\n  a,\n  c,\n]"),
+      "  [\n    a,\n-   b,\n+   c,\n  ]\n"
+    );
+  }
+}
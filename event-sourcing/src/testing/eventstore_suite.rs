@@ -0,0 +1,228 @@
+use std::fmt::Debug;
+
+use futures::StreamExt;
+
+use crate::testing::TodoEvent;
+use crate::{AppendError, Eventstore, Metadata, PersistedEvent, Version, VersionSelect};
+
+/// Title update of `aggregate_id` at `version`, with a title derived from the version.
+pub fn title_updated(aggregate_id: &str, version: Version) -> PersistedEvent<TodoEvent> {
+  PersistedEvent {
+    aggregate_id: aggregate_id.to_string(),
+    version,
+    event: TodoEvent::TodoTitleUpdated {
+      title: format!("title {}", version),
+    },
+    metadata: Metadata::default(),
+  }
+}
+
+/// Behavior every `Eventstore` is expected to share. `make` is called once per case and must
+/// return an empty store. Panics on the first case that fails.
+///
+/// ```
+/// use geeks_event_sourcing::testing::{run_eventstore_suite, InMemoryEventstore};
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// runtime.block_on(run_eventstore_suite(InMemoryEventstore::default));
+/// ```
+pub async fn run_eventstore_suite<E, F>(make: F)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+  F: Fn() -> E,
+{
+  should_append_when_expected_version_matches(make()).await;
+  should_reject_append_on_stale_version(make()).await;
+  should_reject_append_of_foreign_or_gapped_events(make()).await;
+  should_read_all_events_in_append_order(make()).await;
+  should_read_selected_versions(make()).await;
+  should_stream_selected_versions(make()).await;
+  should_resolve_timestamps(make()).await;
+}
+
+async fn should_append_when_expected_version_matches<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  eventstore
+    .append_expected("todo1".to_string(), 0, vec![title_updated("todo1", 1)])
+    .await
+    .unwrap();
+  eventstore
+    .append_expected("todo1".to_string(), 1, vec![title_updated("todo1", 2)])
+    .await
+    .unwrap();
+
+  let events = eventstore
+    .read("todo1".to_string(), VersionSelect::All)
+    .await
+    .unwrap();
+  assert_eq!(
+    events,
+    vec![title_updated("todo1", 1), title_updated("todo1", 2)]
+  );
+}
+
+async fn should_reject_append_on_stale_version<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  eventstore
+    .append(vec![title_updated("todo1", 1)])
+    .await
+    .unwrap();
+
+  let err = eventstore
+    .append_expected("todo1".to_string(), 0, vec![title_updated("todo1", 1)])
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    AppendError::VersionConflict {
+      expected: 0,
+      actual: 1,
+      ..
+    }
+  ));
+}
+
+async fn should_reject_append_of_foreign_or_gapped_events<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  let err = eventstore
+    .append_expected("todo1".to_string(), 0, vec![title_updated("todo2", 1)])
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    AppendError::UnexpectedEvent { version: 1, .. }
+  ));
+  let err = eventstore
+    .append_expected(
+      "todo1".to_string(),
+      0,
+      vec![title_updated("todo1", 1), title_updated("todo1", 3)],
+    )
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    AppendError::UnexpectedEvent {
+      version: 2,
+      found_version: 3,
+      ..
+    }
+  ));
+  assert!(eventstore.read_all(0).await.unwrap().is_empty());
+}
+
+async fn should_read_all_events_in_append_order<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  eventstore
+    .append(vec![
+      title_updated("todo1", 1),
+      title_updated("todo2", 1),
+      title_updated("todo1", 2),
+    ])
+    .await
+    .unwrap();
+
+  let events = eventstore.read_all(0).await.unwrap();
+  let positions: Vec<_> = events.iter().map(|x| x.position).collect();
+  assert_eq!(positions, vec![1, 2, 3]);
+  assert_eq!(events[1].persisted, title_updated("todo2", 1));
+
+  let events = eventstore.read_all(2).await.unwrap();
+  assert_eq!(events.len(), 1);
+  assert_eq!(events[0].position, 3);
+  assert_eq!(events[0].persisted, title_updated("todo1", 2));
+
+  let ids = eventstore.aggregate_ids().await.unwrap();
+  assert_eq!(ids, vec!["todo1".to_string(), "todo2".to_string()]);
+}
+
+async fn should_read_selected_versions<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  eventstore
+    .append((1..=5).map(|x| title_updated("todo1", x)).collect())
+    .await
+    .unwrap();
+
+  let eventstore = &eventstore;
+  let read = |select| async move {
+    let events = eventstore.read("todo1".to_string(), select).await.unwrap();
+    events.iter().map(|x| x.version).collect::<Vec<_>>()
+  };
+  assert_eq!(read(VersionSelect::All).await, vec![1, 2, 3, 4, 5]);
+  assert_eq!(read(VersionSelect::From(4)).await, vec![4, 5]);
+  assert_eq!(read(VersionSelect::UpTo(2)).await, vec![1, 2]);
+  assert_eq!(read(VersionSelect::Range(2, 4)).await, vec![2, 3, 4]);
+  assert_eq!(read(VersionSelect::Last(2)).await, vec![4, 5]);
+  assert_eq!(read(VersionSelect::Last(10)).await, vec![1, 2, 3, 4, 5]);
+  assert!(read(VersionSelect::Last(0)).await.is_empty());
+  assert!(read(VersionSelect::From(6)).await.is_empty());
+}
+
+async fn should_stream_selected_versions<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  eventstore
+    .append(vec![
+      title_updated("todo1", 1),
+      title_updated("todo2", 1),
+      title_updated("todo1", 2),
+      title_updated("todo1", 3),
+    ])
+    .await
+    .unwrap();
+
+  let eventstore = &eventstore;
+  let stream = |select| async move {
+    eventstore
+      .stream("todo1".to_string(), select)
+      .map(|x| x.unwrap().version)
+      .collect::<Vec<_>>()
+      .await
+  };
+  assert_eq!(stream(VersionSelect::All).await, vec![1, 2, 3]);
+  assert_eq!(stream(VersionSelect::From(2)).await, vec![2, 3]);
+  assert_eq!(stream(VersionSelect::UpTo(2)).await, vec![1, 2]);
+  assert_eq!(stream(VersionSelect::Last(1)).await, vec![3]);
+}
+
+async fn should_resolve_timestamps<E>(eventstore: E)
+where
+  E: Eventstore<Event = TodoEvent>,
+  E::Error: Debug,
+{
+  let events = (1..=3)
+    .map(|version| PersistedEvent {
+      metadata: Metadata {
+        recorded_at: Some(version as i64 * 100),
+        ..Metadata::default()
+      },
+      ..title_updated("todo1", version)
+    })
+    .collect();
+  eventstore.append(events).await.unwrap();
+
+  let version_at = |at| eventstore.version_at("todo1".to_string(), at);
+  assert_eq!(version_at(50).await.unwrap(), 0);
+  assert_eq!(version_at(250).await.unwrap(), 2);
+  assert_eq!(version_at(300).await.unwrap(), 3);
+  assert_eq!(eventstore.position_at(50).await.unwrap(), 0);
+  assert_eq!(eventstore.position_at(300).await.unwrap(), 3);
+}
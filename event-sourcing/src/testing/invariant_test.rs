@@ -0,0 +1,275 @@
+use std::fmt::Debug;
+
+use proptest::collection::vec;
+use proptest::strategy::Strategy;
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+
+use crate::{Aggregate, AggregateRoot, Command, PersistedEvent};
+
+type Invariant<T> = Box<dyn Fn(&AggregateRoot<T>) -> bool>;
+
+/// Runs random command sequences against an `AggregateRoot` and checks invariants after
+/// every command. Besides the user supplied ones, it checks that versions grow one by one
+/// and that replaying every produced event with `save_events` yields the same root.
+/// Failing sequences are shrunk before being reported.
+pub struct InvariantTest<T, S>
+where
+  T: Aggregate,
+{
+  commands: S,
+  max_commands: usize,
+  cases: u32,
+  invariants: Vec<(String, Invariant<T>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantFailure<C> {
+  pub reason: String,
+  /// The smallest failing command sequence found.
+  pub commands: Vec<C>,
+}
+
+impl<T, S> InvariantTest<T, S>
+where
+  T: Aggregate + Debug + PartialEq,
+  T::Command: Debug,
+  T::Event: Debug,
+  T::Error: Debug,
+  S: Strategy<Value = T::Command>,
+{
+  pub fn new(commands: S) -> Self {
+    Self {
+      commands,
+      max_commands: 32,
+      cases: 256,
+      invariants: Vec::new(),
+    }
+  }
+
+  #[must_use]
+  pub fn max_commands(mut self, max_commands: usize) -> Self {
+    self.max_commands = max_commands;
+    self
+  }
+
+  #[must_use]
+  pub fn cases(mut self, cases: u32) -> Self {
+    self.cases = cases;
+    self
+  }
+
+  #[must_use]
+  pub fn invariant<N, F>(mut self, name: N, check: F) -> Self
+  where
+    N: Into<String>,
+    F: Fn(&AggregateRoot<T>) -> bool + 'static,
+  {
+    self.invariants.push((name.into(), Box::new(check)));
+    self
+  }
+
+  /// Panics with the reason and the shrunk command sequence if an invariant is broken.
+  pub fn run(self) {
+    if let Err(failure) = self.check() {
+      panic!(
+        "invariant broken: {}\nminimal failing commands: {:#?}",
+        failure.reason, failure.commands
+      );
+    }
+  }
+
+  pub fn check(self) -> Result<(), InvariantFailure<T::Command>> {
+    let mut runner = TestRunner::new(Config {
+      cases: self.cases,
+      failure_persistence: None,
+      ..Config::default()
+    });
+    let strategy = vec(self.commands, 0..=self.max_commands);
+    let invariants = self.invariants;
+
+    match runner.run(&strategy, |commands| {
+      check_sequence(commands, &invariants).map_err(TestCaseError::fail)
+    }) {
+      Ok(()) => Ok(()),
+      Err(TestError::Fail(reason, commands)) => Err(InvariantFailure {
+        reason: reason.message().to_owned(),
+        commands,
+      }),
+      Err(TestError::Abort(reason)) => Err(InvariantFailure {
+        reason: reason.message().to_owned(),
+        commands: Vec::new(),
+      }),
+    }
+  }
+}
+
+fn check_sequence<T>(
+  commands: Vec<T::Command>,
+  invariants: &[(String, Invariant<T>)],
+) -> Result<(), String>
+where
+  T: Aggregate + Debug + PartialEq,
+  T::Command: Debug,
+  T::Event: Debug,
+  T::Error: Debug,
+{
+  let mut root: AggregateRoot<T> = AggregateRoot::default();
+  let mut produced: Vec<PersistedEvent<T::Event>> = Vec::new();
+
+  for (index, command) in commands.into_iter().enumerate() {
+    let id = command.aggregate_id().to_owned();
+    let before = root.clone();
+    let previous = root.get_version(&id).cloned().unwrap_or(0);
+
+    match root.execute_command(command) {
+      Ok(events) => {
+        for (offset, event) in events.iter().enumerate() {
+          let expected = previous + offset as u64 + 1;
+          if event.aggregate_id != id || event.version != expected {
+            return Err(format!(
+              "command #{} produced {}@{}, expected {}@{}",
+              index, event.aggregate_id, event.version, id, expected
+            ));
+          }
+        }
+        produced.extend(events);
+      }
+      Err(_) => {
+        if root.states != before.states || root.versions != before.versions {
+          return Err(format!("rejected command #{} changed the root", index));
+        }
+      }
+    }
+
+    for (name, check) in invariants {
+      if !check(&root) {
+        return Err(format!("\"{}\" after command #{}", name, index));
+      }
+    }
+  }
+
+  let mut replayed: AggregateRoot<T> = AggregateRoot::default();
+  replayed
+    .save_events(produced)
+    .map_err(|e| format!("replaying produced events failed: {:?}", e))?;
+  if replayed.states != root.states || replayed.versions != root.versions {
+    return Err(format!(
+      "replaying produced events yields a different root: {:?} != {:?}",
+      replayed.states, root.states
+    ));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use proptest::prelude::*;
+  use serde::{Deserialize, Serialize};
+
+  use super::{check_sequence, Invariant};
+  use crate::testing::InvariantTest;
+  use crate::{Aggregate, AggregateRoot, Command, Event};
+
+  #[derive(Debug, Clone, PartialEq)]
+  struct Counter {
+    id: String,
+    value: u32,
+  }
+
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Command)]
+  enum CounterCommand {
+    Increment { id: String, by: u32 },
+    Decrement { id: String, by: u32 },
+  }
+
+  #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Event)]
+  enum CounterEvent {
+    Incremented { by: u32 },
+    Decremented { by: u32 },
+  }
+
+  #[derive(Debug, PartialEq)]
+  struct Underflow;
+
+  impl Aggregate for Counter {
+    type Command = CounterCommand;
+    type Event = CounterEvent;
+    type Error = Underflow;
+
+    fn id(&self) -> &str {
+      &self.id
+    }
+
+    fn handle_command(
+      this: Option<&Self>,
+      command: Self::Command,
+    ) -> Result<Vec<Self::Event>, Self::Error> {
+      let value = this.map(|x| x.value).unwrap_or(0);
+      match command {
+        CounterCommand::Increment { by, .. } => Ok(vec![CounterEvent::Incremented { by }]),
+        CounterCommand::Decrement { by, .. } if by > value => Err(Underflow),
+        CounterCommand::Decrement { by, .. } => Ok(vec![CounterEvent::Decremented { by }]),
+      }
+    }
+
+    fn apply_event(this: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+      let mut counter = this.unwrap_or(Counter {
+        id: String::new(),
+        value: 0,
+      });
+      match event {
+        CounterEvent::Incremented { by } => counter.value += by,
+        CounterEvent::Decremented { by } => counter.value -= by,
+      }
+      Ok(counter)
+    }
+  }
+
+  fn commands() -> impl Strategy<Value = CounterCommand> {
+    let id = prop_oneof![Just("counter_0".to_string()), Just("counter_1".to_string())];
+    (id, 0..10u32, any::<bool>()).prop_map(|(id, by, increment)| match increment {
+      true => CounterCommand::Increment { id, by },
+      false => CounterCommand::Decrement { id, by },
+    })
+  }
+
+  #[test]
+  fn should_hold_invariants() {
+    InvariantTest::<Counter, _>::new(commands())
+      .invariant(
+        "counters stay below a hundred times their version",
+        |root| {
+          root
+            .states
+            .iter()
+            .all(|(id, x)| u64::from(x.value) < 100 * root.get_version(id).unwrap())
+        },
+      )
+      .run();
+  }
+
+  #[test]
+  fn should_shrink_failing_command_sequence() {
+    let below_ten = || -> Vec<(String, Invariant<Counter>)> {
+      vec![(
+        "counters stay below ten".to_string(),
+        Box::new(|root: &AggregateRoot<Counter>| root.states.values().all(|x| x.value < 10)),
+      )]
+    };
+    let failure = InvariantTest::<Counter, _>::new(commands())
+      .invariant("counters stay below ten", |root| {
+        root.states.values().all(|x| x.value < 10)
+      })
+      .check()
+      .unwrap_err();
+
+    let last = failure.commands.len() - 1;
+    assert_eq!(
+      failure.reason,
+      format!("\"counters stay below ten\" after command #{}", last)
+    );
+    assert!(check_sequence(failure.commands.clone(), &below_ten()).is_err());
+    assert!(check_sequence(failure.commands[..last].to_vec(), &below_ten()).is_ok());
+  }
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::{
+  convert::Infallible,
+  sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+
+use crate::{CheckpointStore, Position};
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCheckpointStore {
+  checkpoints: Arc<RwLock<HashMap<String, Position>>>,
+}
+
+#[async_trait]
+impl CheckpointStore for InMemoryCheckpointStore {
+  type Error = Infallible;
+
+  async fn load(&self, name: &str) -> Result<Position, Self::Error> {
+    let checkpoints = self.checkpoints.read().expect("locked");
+    Ok(checkpoints.get(name).cloned().unwrap_or(0))
+  }
+
+  async fn save(&self, name: &str, position: Position) -> Result<(), Self::Error> {
+    let mut checkpoints = self
+      .checkpoints
+      .write()
+      .expect("acquire write lock on checkpoint store");
+    checkpoints.insert(name.to_owned(), position);
+
+    Ok(())
+  }
+}
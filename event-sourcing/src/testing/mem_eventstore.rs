@@ -6,14 +6,18 @@ use std::{
 
 use async_trait::async_trait;
 
-use crate::{Event, Eventstore, PersistedEvent, VersionSelect};
+use crate::{
+  AppendError, Event, EventBus, EventPublisher, Eventstore, PersistedEvent, Position,
+  PositionedEvent, Version, VersionSelect,
+};
 
 #[derive(Debug)]
 struct InMemoryBackend<T>
 where
   T: Event,
 {
-  events: HashMap<String, Vec<PersistedEvent<T>>>,
+  log: Vec<PersistedEvent<T>>,
+  streams: HashMap<String, Vec<usize>>,
 }
 
 impl<T> Default for InMemoryBackend<T>
@@ -22,7 +26,36 @@ where
 {
   fn default() -> Self {
     Self {
-      events: HashMap::default(),
+      log: Vec::new(),
+      streams: HashMap::default(),
+    }
+  }
+}
+
+impl<T> InMemoryBackend<T>
+where
+  T: Event,
+{
+  fn stream<'a>(&'a self, aggregate_id: &str) -> impl Iterator<Item = &'a PersistedEvent<T>> {
+    self
+      .streams
+      .get(aggregate_id)
+      .into_iter()
+      .flatten()
+      .map(|index| &self.log[*index])
+  }
+
+  fn push(&mut self, event: PersistedEvent<T>) -> PositionedEvent<T> {
+    self
+      .streams
+      .entry(event.aggregate_id.to_owned())
+      .or_default()
+      .push(self.log.len());
+    self.log.push(event.clone());
+
+    PositionedEvent {
+      position: self.log.len() as Position,
+      persisted: event,
     }
   }
 }
@@ -33,6 +66,7 @@ where
   T: Event,
 {
   backend: Arc<RwLock<InMemoryBackend<T>>>,
+  event_bus: EventBus<T>,
 }
 
 impl<T> Default for InMemoryEventstore<T>
@@ -42,6 +76,7 @@ where
   fn default() -> Self {
     Self {
       backend: Arc::default(),
+      event_bus: EventBus::default(),
     }
   }
 }
@@ -60,17 +95,14 @@ where
     select: VersionSelect,
   ) -> Result<Vec<PersistedEvent<Self::Event>>, Self::Error> {
     let backend = self.backend.read().expect("locked");
-    let events: Vec<_> = backend
-      .events
-      .get(&aggregate_id)
+    let mut events: Vec<_> = backend
+      .stream(&aggregate_id)
+      .filter(|event| select.contains(event.version))
       .cloned()
-      .unwrap_or_default()
-      .into_iter()
-      .filter(|event| match select {
-        VersionSelect::All => true,
-        VersionSelect::From(v) => event.version >= v,
-      })
       .collect();
+    if let VersionSelect::Last(n) = select {
+      events.drain(..events.len().saturating_sub(n as usize));
+    }
 
     Ok(events)
   }
@@ -81,14 +113,87 @@ where
       .write()
       .expect("acquire write lock on event store backend");
 
-    events.into_iter().for_each(|event| {
-      backend
-        .events
-        .entry(event.aggregate_id.to_owned())
-        .and_modify(|x| x.push(event.clone()))
-        .or_insert_with(|| vec![event]);
-    });
+    let appended = events.into_iter().map(|x| backend.push(x)).collect();
+    drop(backend);
+    self.event_bus.publish(appended);
 
     Ok(())
   }
+
+  async fn append_expected(
+    &self,
+    aggregate_id: String,
+    expected: Version,
+    events: Vec<PersistedEvent<Self::Event>>,
+  ) -> Result<(), AppendError<Self::Error>> {
+    AppendError::check_events(&aggregate_id, expected, &events)?;
+    let mut backend = self
+      .backend
+      .write()
+      .expect("acquire write lock on event store backend");
+
+    let actual = backend
+      .stream(&aggregate_id)
+      .last()
+      .map(|x| x.version)
+      .unwrap_or(0);
+    if actual != expected {
+      return Err(AppendError::VersionConflict {
+        aggregate_id,
+        expected,
+        actual,
+      });
+    }
+    let appended = events.into_iter().map(|x| backend.push(x)).collect();
+    drop(backend);
+    self.event_bus.publish(appended);
+
+    Ok(())
+  }
+
+  async fn read_all(
+    &self,
+    after: Position,
+  ) -> Result<Vec<PositionedEvent<Self::Event>>, Self::Error> {
+    let backend = self.backend.read().expect("locked");
+    let events = backend
+      .log
+      .iter()
+      .enumerate()
+      .skip(after as usize)
+      .map(|(index, persisted)| PositionedEvent {
+        position: index as Position + 1,
+        persisted: persisted.clone(),
+      })
+      .collect();
+
+    Ok(events)
+  }
+
+  async fn aggregate_ids(&self) -> Result<Vec<String>, Self::Error> {
+    let backend = self.backend.read().expect("locked");
+    let mut ids: Vec<_> = backend.streams.iter().collect();
+    ids.sort_by_key(|(_, indexes)| indexes[0]);
+
+    Ok(ids.into_iter().map(|(id, _)| id.to_owned()).collect())
+  }
+}
+
+impl<T> EventPublisher for InMemoryEventstore<T>
+where
+  T: Event + Clone,
+{
+  fn event_bus(&self) -> &EventBus<T> {
+    &self.event_bus
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::testing::{run_eventstore_suite, InMemoryEventstore};
+
+  #[tokio::test]
+  async fn should_behave_like_an_eventstore() {
+    run_eventstore_suite(InMemoryEventstore::default).await;
+  }
 }
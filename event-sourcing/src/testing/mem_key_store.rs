@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::{generate_key, EncryptionKey, KeyStore, ShreddingError};
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryKeyStore {
+  keys: Arc<RwLock<HashMap<String, EncryptionKey>>>,
+}
+
+impl KeyStore for InMemoryKeyStore {
+  fn key(&self, subject: &str) -> Result<Option<EncryptionKey>, ShreddingError> {
+    Ok(self.keys.read().expect("locked").get(subject).cloned())
+  }
+
+  fn get_or_create_key(&self, subject: &str) -> Result<EncryptionKey, ShreddingError> {
+    let mut keys = self.keys.write().expect("acquire write lock on keys");
+    Ok(*keys.entry(subject.to_string()).or_insert_with(generate_key))
+  }
+
+  fn delete_key(&self, subject: &str) -> Result<(), ShreddingError> {
+    self
+      .keys
+      .write()
+      .expect("acquire write lock on keys")
+      .remove(subject);
+    Ok(())
+  }
+}
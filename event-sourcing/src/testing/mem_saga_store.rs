@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{Saga, SagaRecord, SagaStore};
+
+type Records<G> = HashMap<String, SagaRecord<<G as Saga>::State, <G as Saga>::Command>>;
+
+pub struct InMemorySagaStore<G>
+where
+  G: Saga,
+{
+  records: Arc<RwLock<Records<G>>>,
+}
+
+impl<G> Default for InMemorySagaStore<G>
+where
+  G: Saga,
+{
+  fn default() -> Self {
+    Self {
+      records: Arc::default(),
+    }
+  }
+}
+
+impl<G> Clone for InMemorySagaStore<G>
+where
+  G: Saga,
+{
+  fn clone(&self) -> Self {
+    Self {
+      records: self.records.clone(),
+    }
+  }
+}
+
+#[async_trait]
+impl<G> SagaStore<G> for InMemorySagaStore<G>
+where
+  G: Saga,
+{
+  type Error = Infallible;
+
+  async fn load(&self, name: &str) -> Result<SagaRecord<G::State, G::Command>, Self::Error> {
+    let records = self.records.read().expect("locked");
+    Ok(records.get(name).cloned().unwrap_or_default())
+  }
+
+  async fn save(
+    &self,
+    name: &str,
+    record: SagaRecord<G::State, G::Command>,
+  ) -> Result<(), Self::Error> {
+    let mut records = self
+      .records
+      .write()
+      .expect("acquire write lock on saga store");
+    records.insert(name.to_owned(), record);
+
+    Ok(())
+  }
+}
@@ -0,0 +1,53 @@
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{Aggregate, AggregateRoot, Snapshot};
+
+#[derive(Debug, Clone)]
+pub struct InMemorySnapshot<T>
+where
+  T: Aggregate,
+{
+  root: Arc<RwLock<Option<AggregateRoot<T>>>>,
+}
+
+impl<T> Default for InMemorySnapshot<T>
+where
+  T: Aggregate,
+{
+  fn default() -> Self {
+    Self {
+      root: Arc::default(),
+    }
+  }
+}
+
+impl<T> InMemorySnapshot<T>
+where
+  T: Aggregate,
+{
+  pub fn saved(&self) -> Option<AggregateRoot<T>> {
+    self.root.read().expect("locked").clone()
+  }
+}
+
+#[async_trait]
+impl<T> Snapshot<T> for InMemorySnapshot<T>
+where
+  T: Aggregate,
+{
+  type Error = Infallible;
+
+  async fn load(&self) -> Result<AggregateRoot<T>, Self::Error> {
+    Ok(self.saved().unwrap_or_default())
+  }
+
+  async fn save(&self, root: AggregateRoot<T>) -> Result<(), Self::Error> {
+    let mut saved = self.root.write().expect("acquire write lock on snapshot");
+    *saved = Some(root);
+
+    Ok(())
+  }
+}
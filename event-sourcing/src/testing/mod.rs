@@ -1,5 +1,21 @@
+pub use self::aggregate_test::*;
+pub use self::eventstore_suite::*;
+#[cfg(feature = "proptest")]
+pub use self::invariant_test::*;
+pub use self::mem_checkpoint_store::*;
 pub use self::mem_eventstore::*;
+pub use self::mem_key_store::*;
+pub use self::mem_saga_store::*;
+pub use self::mem_snapshot::*;
 pub use self::todo_domain::*;
 
+mod aggregate_test;
+mod eventstore_suite;
+#[cfg(feature = "proptest")]
+mod invariant_test;
+mod mem_checkpoint_store;
 mod mem_eventstore;
+mod mem_key_store;
+mod mem_saga_store;
+mod mem_snapshot;
 mod todo_domain;
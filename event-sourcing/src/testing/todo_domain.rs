@@ -1,17 +1,10 @@
-use std::collections::HashMap;
-use std::io;
-use std::path::{Path, PathBuf};
-use std::str::{from_utf8, Utf8Error};
-
-use async_trait::async_trait;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_value};
-use tokio::fs::{read, write};
 
-use crate::{Aggregate, AggregateRoot, Command, Event, Snapshot, Timestamp, Version};
+use crate::testing::{InMemoryEventstore, InMemorySnapshot};
+use crate::{Aggregate, AggregateRoot, Command, Dispatcher, Event, FsSnapshot, Timestamp};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Event)]
 #[serde(tag = "name")]
 pub enum TodoEvent {
   TodoCreated {
@@ -27,17 +20,7 @@ pub enum TodoEvent {
   },
 }
 
-impl Event for TodoEvent {
-  fn name(&self) -> &'static str {
-    match self {
-      TodoEvent::TodoCreated { .. } => "TodoCreated",
-      TodoEvent::TodoTitleUpdated { .. } => "TodoTitleUpdated",
-      TodoEvent::TodoStatusUpdated { .. } => "TodoStatusUpdated",
-    }
-  }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Command)]
 #[serde(tag = "name")]
 pub enum TodoCommand {
   CreateTodo {
@@ -53,24 +36,14 @@ pub enum TodoCommand {
     id: String,
     status: TodoStatus,
   },
-}
-
-impl Command for TodoCommand {
-  fn name(&self) -> &'static str {
-    match self {
-      TodoCommand::CreateTodo { .. } => "CreateTodo",
-      TodoCommand::UpdateTodoTitle { .. } => "UpdateTodoTitle",
-      TodoCommand::UpdateTodoStatus { .. } => "UpdateTodoStatus",
-    }
-  }
-
-  fn aggregate_id(&self) -> &str {
-    match self {
-      TodoCommand::CreateTodo { id, .. } => id,
-      TodoCommand::UpdateTodoTitle { id, .. } => id,
-      TodoCommand::UpdateTodoStatus { id, .. } => id,
-    }
-  }
+  UpdateTodo {
+    id: String,
+    title: Option<String>,
+    status: Option<TodoStatus>,
+    #[serde(default)]
+    #[command(idempotency_key)]
+    request_id: Option<String>,
+  },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -89,6 +62,8 @@ pub enum TodoError {
   AlreadyExists,
   #[error("Todo not exists")]
   NotExists,
+  #[error("Not authorized")]
+  Unauthorized,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,21 +87,35 @@ impl Aggregate for Todo {
   fn handle_command(
     this: Option<&Self>,
     command: Self::Command,
-  ) -> Result<Self::Event, Self::Error> {
+  ) -> Result<Vec<Self::Event>, Self::Error> {
     match command {
       TodoCommand::CreateTodo { id, title, status } => {
         if this.is_some() {
           return Err(TodoError::AlreadyExists);
         }
 
-        Ok(TodoEvent::TodoCreated {
+        Ok(vec![TodoEvent::TodoCreated {
           id,
           title,
           status: status.unwrap_or(TodoStatus::Todo),
-        })
+        }])
+      }
+      TodoCommand::UpdateTodoTitle { title, .. } => Ok(vec![TodoEvent::TodoTitleUpdated { title }]),
+      TodoCommand::UpdateTodoStatus { status, .. } => {
+        Ok(vec![TodoEvent::TodoStatusUpdated { status }])
+      }
+      TodoCommand::UpdateTodo { title, status, .. } => {
+        let todo = this.ok_or(TodoError::NotExists)?;
+        let mut events = Vec::new();
+        if let Some(title) = title.filter(|x| x != &todo.title) {
+          events.push(TodoEvent::TodoTitleUpdated { title });
+        }
+        if let Some(status) = status.filter(|x| x != &todo.status) {
+          events.push(TodoEvent::TodoStatusUpdated { status });
+        }
+
+        Ok(events)
       }
-      TodoCommand::UpdateTodoTitle { title, .. } => Ok(TodoEvent::TodoTitleUpdated { title }),
-      TodoCommand::UpdateTodoStatus { status, .. } => Ok(TodoEvent::TodoStatusUpdated { status }),
     }
   }
 
@@ -164,66 +153,22 @@ impl Aggregate for Todo {
   }
 }
 
-pub struct TodoSnapshot {
-  file_path: PathBuf,
-}
+pub type TodoSnapshot = FsSnapshot<Todo>;
 
-impl TodoSnapshot {
-  pub fn new(dir: &Path) -> Self {
-    Self {
-      file_path: dir.join("todo"),
-    }
-  }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TodoSnapshotData {
-  states: HashMap<String, Todo>,
-  versions: HashMap<String, Version>,
-}
-
-impl From<AggregateRoot<Todo>> for TodoSnapshotData {
-  fn from(root: AggregateRoot<Todo>) -> Self {
-    Self {
-      states: root.states,
-      versions: root.versions,
-    }
+/// Creates `id` with a fixed title.
+pub fn create_todo(id: &str) -> TodoCommand {
+  TodoCommand::CreateTodo {
+    id: id.to_string(),
+    title: "Drink coffee".to_string(),
+    status: None,
   }
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum TodoSnapshotError {
-  #[error("io error: {0}")]
-  IoError(#[source] io::Error),
-
-  #[error("utf8 error: {0}")]
-  Utf8Error(#[source] Utf8Error),
-
-  #[error("json parse error: {0}")]
-  JsonParseError(#[source] serde_json::Error),
-}
-
-#[async_trait]
-impl Snapshot<Todo> for TodoSnapshot {
-  type Error = TodoSnapshotError;
-
-  async fn load(&self) -> Result<AggregateRoot<Todo>, Self::Error> {
-    let raw = read(&self.file_path)
-      .await
-      .map_err(TodoSnapshotError::IoError)?;
-    let raw_str = from_utf8(&raw).map_err(TodoSnapshotError::Utf8Error)?;
-    let data = from_str::<TodoSnapshotData>(raw_str).map_err(TodoSnapshotError::JsonParseError)?;
-
-    Ok(AggregateRoot::new(data.states, data.versions))
-  }
-
-  async fn save(&self, root: AggregateRoot<Todo>) -> Result<(), Self::Error> {
-    let data: TodoSnapshotData = root.into();
-    let raw_data = to_value(data).map_err(TodoSnapshotError::JsonParseError)?;
-    write(&self.file_path, raw_data.to_string().as_bytes())
-      .await
-      .map_err(TodoSnapshotError::IoError)?;
-
-    Ok(())
-  }
+/// Dispatcher over an empty root, eventstore and snapshot, all kept in memory.
+pub fn dispatcher() -> Dispatcher<Todo, InMemoryEventstore<TodoEvent>, InMemorySnapshot<Todo>> {
+  Dispatcher::new(
+    AggregateRoot::default(),
+    InMemoryEventstore::default(),
+    InMemorySnapshot::default(),
+  )
 }
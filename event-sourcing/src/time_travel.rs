@@ -0,0 +1,189 @@
+use std::convert::Infallible;
+
+use crate::{
+  Aggregate, AggregateRoot, Error, Eventstore, Position, Timestamp, Version, VersionSelect,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+  Version(Version),
+  Timestamp(Timestamp),
+}
+
+/// Rebuilds a single aggregate as it was at `as_of` by replaying its events.
+pub async fn load_aggregate_at<T, E>(
+  eventstore: &E,
+  aggregate_id: &str,
+  as_of: AsOf,
+) -> Result<AggregateRoot<T>, Error<T::Error, E::Error, Infallible>>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+{
+  let version = match as_of {
+    AsOf::Version(v) => v,
+    AsOf::Timestamp(t) => eventstore
+      .version_at(aggregate_id.to_owned(), t)
+      .await
+      .map_err(Error::EventstoreError)?,
+  };
+
+  let mut root = AggregateRoot::default();
+  if version == 0 {
+    return Ok(root);
+  }
+
+  let events = eventstore
+    .read(aggregate_id.to_owned(), VersionSelect::UpTo(version))
+    .await
+    .map_err(Error::EventstoreError)?;
+  root.save_events(events).map_err(Error::AggregateError)?;
+
+  Ok(root)
+}
+
+/// Rebuilds every aggregate as it was at `at` by replaying the global event stream.
+pub async fn load_root_at<T, E>(
+  eventstore: &E,
+  at: Timestamp,
+) -> Result<AggregateRoot<T>, Error<T::Error, E::Error, Infallible>>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+{
+  let position = eventstore
+    .position_at(at)
+    .await
+    .map_err(Error::EventstoreError)?;
+  load_root_at_position(eventstore, position).await
+}
+
+/// Rebuilds every aggregate from the events up to the global `position`, inclusive.
+pub async fn load_root_at_position<T, E>(
+  eventstore: &E,
+  position: Position,
+) -> Result<AggregateRoot<T>, Error<T::Error, E::Error, Infallible>>
+where
+  T: Aggregate,
+  E: Eventstore<Event = T::Event>,
+{
+  let events = eventstore
+    .read_all(0)
+    .await
+    .map_err(Error::EventstoreError)?
+    .into_iter()
+    .take_while(|x| x.position <= position)
+    .map(|x| x.persisted)
+    .collect();
+
+  let mut root = AggregateRoot::default();
+  root.save_events(events).map_err(Error::AggregateError)?;
+
+  Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::testing::{InMemoryEventstore, Todo, TodoCommand, TodoEvent, TodoStatus};
+  use crate::{
+    load_aggregate_at, load_root_at, load_root_at_position, AggregateRoot, AsOf, Eventstore,
+    Metadata,
+  };
+
+  async fn setup() -> InMemoryEventstore<TodoEvent> {
+    let eventstore = InMemoryEventstore::default();
+    let mut root: AggregateRoot<Todo> = AggregateRoot::default();
+    let commands = vec![
+      (
+        100,
+        TodoCommand::CreateTodo {
+          id: "todo1".to_string(),
+          title: "Drink coffee".to_string(),
+          status: None,
+        },
+      ),
+      (
+        200,
+        TodoCommand::UpdateTodoTitle {
+          id: "todo1".to_string(),
+          title: "Eat pizza".to_string(),
+        },
+      ),
+      (
+        300,
+        TodoCommand::CreateTodo {
+          id: "todo2".to_string(),
+          title: "Sleep".to_string(),
+          status: None,
+        },
+      ),
+      (
+        400,
+        TodoCommand::UpdateTodoStatus {
+          id: "todo1".to_string(),
+          status: TodoStatus::Done,
+        },
+      ),
+    ];
+    for (recorded_at, command) in commands {
+      let metadata = Metadata {
+        recorded_at: Some(recorded_at),
+        ..Metadata::default()
+      };
+      let events = root
+        .execute_command_with_metadata(command, metadata)
+        .unwrap();
+      eventstore.append(events).await.unwrap();
+    }
+
+    eventstore
+  }
+
+  #[tokio::test]
+  async fn should_load_aggregate_as_of_version() {
+    let eventstore = setup().await;
+    let root: AggregateRoot<Todo> = load_aggregate_at(&eventstore, "todo1", AsOf::Version(2))
+      .await
+      .unwrap();
+
+    let todo = root.get_state("todo1").unwrap();
+    assert_eq!(todo.title, "Eat pizza");
+    assert_eq!(todo.status, TodoStatus::Todo);
+    assert_eq!(root.get_version("todo1"), Some(&2));
+  }
+
+  #[tokio::test]
+  async fn should_load_aggregate_as_of_timestamp() {
+    let eventstore = setup().await;
+    let root: AggregateRoot<Todo> = load_aggregate_at(&eventstore, "todo1", AsOf::Timestamp(150))
+      .await
+      .unwrap();
+    assert_eq!(root.get_state("todo1").unwrap().title, "Drink coffee");
+
+    let root: AggregateRoot<Todo> = load_aggregate_at(&eventstore, "todo1", AsOf::Timestamp(50))
+      .await
+      .unwrap();
+    assert!(root.get_state("todo1").is_none());
+  }
+
+  #[tokio::test]
+  async fn should_load_root_as_of_timestamp() {
+    let eventstore = setup().await;
+    let root: AggregateRoot<Todo> = load_root_at(&eventstore, 350).await.unwrap();
+
+    assert_eq!(root.get_version("todo1"), Some(&2));
+    assert_eq!(root.get_version("todo2"), Some(&1));
+    assert_eq!(root.get_state("todo1").unwrap().status, TodoStatus::Todo);
+  }
+
+  #[tokio::test]
+  async fn should_load_root_as_of_position() {
+    let eventstore = setup().await;
+    let root: AggregateRoot<Todo> = load_root_at_position(&eventstore, 3).await.unwrap();
+    assert_eq!(root.get_version("todo1"), Some(&2));
+    assert_eq!(root.get_version("todo2"), Some(&1));
+
+    let root: AggregateRoot<Todo> = load_root_at_position(&eventstore, 0).await.unwrap();
+    assert!(root.states.is_empty());
+  }
+}
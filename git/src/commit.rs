@@ -1,9 +1,9 @@
 use std::path::Path;
 
-use git2::{Oid, Repository};
+use git2::{ErrorCode, Oid, Repository};
 
 use crate::repository::{get_head, get_signature};
-use crate::GitResult;
+use crate::{GitError, GitResult};
 
 pub fn commit<P, Message>(repo_path: P, message: Message) -> GitResult<Oid>
 where
@@ -34,6 +34,61 @@ where
   Ok(oid)
 }
 
+/// Commits `messages` on top of `parent` and moves HEAD to the last commit only if HEAD still
+/// points at `parent`. Returns `GitError::HeadMoved` when another writer got there first.
+pub fn commit_on_parent<P, Message>(
+  repo_path: P,
+  parent: Option<Oid>,
+  messages: &[Message],
+) -> GitResult<Option<Oid>>
+where
+  P: AsRef<Path>,
+  Message: ToString,
+{
+  let repo = Repository::open(repo_path)?;
+  let sig = get_signature(&repo)?;
+  let mut index = repo.index()?;
+  let tree_id = index.write_tree()?;
+  let tree = repo.find_tree(tree_id)?;
+
+  let mut tip = parent;
+  for message in messages {
+    let parents = match tip {
+      Some(id) => vec![repo.find_commit(id)?],
+      None => Vec::new(),
+    };
+    let parents = parents.iter().collect::<Vec<_>>();
+    let oid = repo.commit(None, &sig, &sig, &message.to_string(), &tree, &parents)?;
+    tip = Some(oid);
+  }
+
+  let new_head = match tip {
+    Some(x) if tip != parent => x,
+    _ => return Ok(None),
+  };
+
+  let head_ref = repo.find_reference("HEAD")?;
+  let ref_name = head_ref.symbolic_target().unwrap_or("HEAD").to_string();
+  let log_message = "commit: geeks";
+  let updated = match parent {
+    Some(current) => repo.reference_matching(&ref_name, new_head, true, current, log_message),
+    None => repo.reference(&ref_name, new_head, false, log_message),
+  };
+
+  match updated {
+    Ok(_) => Ok(Some(new_head)),
+    Err(e)
+      if matches!(
+        e.code(),
+        ErrorCode::Modified | ErrorCode::Exists | ErrorCode::NotFound
+      ) =>
+    {
+      Err(GitError::HeadMoved)
+    }
+    Err(e) => Err(e.into()),
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use geeks_git_testing::FixtureRepository;
@@ -64,4 +119,44 @@ mod tests {
     assert_eq!(commits[1].message, "2".into());
     assert_eq!(commits[2].message, "1".into());
   }
+
+  #[test]
+  fn should_commit_on_parent() {
+    let fixture = FixtureRepository::setup();
+    let first = commit_on_parent(&fixture.path, None, &["1", "2"])
+      .unwrap()
+      .unwrap();
+    commit_on_parent(&fixture.path, Some(first), &["3"]).unwrap();
+
+    let repo = Repository::open(&fixture.path).unwrap();
+    let reader = CommitReader::new(&repo).unwrap();
+    let commits: Vec<_> = reader.map(|x| x.unwrap()).collect();
+
+    assert_eq!(commits.len(), 3);
+    assert_eq!(commits[0].message, "3".into());
+    assert_eq!(commits[2].message, "1".into());
+  }
+
+  #[test]
+  fn should_fail_to_commit_on_stale_parent() {
+    let fixture = FixtureRepository::setup();
+    assert!(matches!(
+      commit_on_parent(&fixture.path, None, &["1"]),
+      Ok(Some(_))
+    ));
+
+    let err = commit_on_parent(&fixture.path, None, &["2"]).unwrap_err();
+    assert!(matches!(err, GitError::HeadMoved));
+
+    let head = commit(&fixture.path, "2").unwrap();
+    let stale = repo_head_parent(&fixture.path, head);
+    let err = commit_on_parent(&fixture.path, Some(stale), &["3"]).unwrap_err();
+    assert!(matches!(err, GitError::HeadMoved));
+  }
+
+  fn repo_head_parent(path: &Path, head: Oid) -> Oid {
+    let repo = Repository::open(path).unwrap();
+    let commit = repo.find_commit(head).unwrap();
+    commit.parent_id(0).unwrap()
+  }
 }
@@ -1,4 +1,4 @@
-use git2::{Error, Oid, Repository, Revwalk};
+use git2::{Error, Oid, Repository, Revwalk, Sort};
 
 use crate::commit_info::CommitInfo;
 use crate::GitResult;
@@ -19,6 +19,8 @@ pub struct CommitReader<'a> {
   revwalk: Revwalk<'a>,
   start_on: CommitReadStartOn,
   end_when: Option<CommitReaderEndWhen>,
+  hidden: Vec<Oid>,
+  reversed: bool,
   started: bool,
 }
 
@@ -31,6 +33,8 @@ impl<'a> CommitReader<'a> {
       revwalk,
       start_on: CommitReadStartOn::Head,
       end_when: None,
+      hidden: Vec::new(),
+      reversed: false,
       started: false,
     })
   }
@@ -61,11 +65,36 @@ impl<'a> CommitReader<'a> {
     }
   }
 
+  /// Skips the given commit and all of its ancestors.
+  #[must_use]
+  pub fn hide_oid(mut self, oid: Oid) -> Self {
+    self.hidden.push(oid);
+    self
+  }
+
+  /// Reads commits from the oldest one instead of the newest one.
+  #[must_use]
+  pub fn reversed(self) -> Self {
+    Self {
+      reversed: true,
+      ..self
+    }
+  }
+
   fn push_start(&mut self) -> Result<(), Error> {
     if self.started {
       return Ok(());
     }
 
+    if self.reversed {
+      self
+        .revwalk
+        .set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    }
+    for oid in &self.hidden {
+      self.revwalk.hide(*oid)?;
+    }
+
     match self.start_on {
       CommitReadStartOn::Head => {
         self.revwalk.push_head()?;
@@ -202,4 +231,30 @@ mod tests {
 
     assert_eq!(commits.len(), 0);
   }
+
+  #[test]
+  fn should_read_commits_reversed_and_hidden() {
+    let fixture = FixtureRepository::setup_with_script(
+      r#"
+      git commit --allow-empty -m "1"
+      git commit --allow-empty -m "2"
+      git commit --allow-empty -m "3"
+      git commit --allow-empty -m "4"
+      "#,
+    );
+    let repo = Repository::open(&fixture.path).unwrap();
+    let reader = CommitReader::new(&repo).unwrap().start_on_head();
+    let commits: Vec<_> = reader.map(|x| x.unwrap()).collect();
+
+    let reader = CommitReader::new(&repo)
+      .unwrap()
+      .start_on_head()
+      .hide_oid(commits[2].id)
+      .reversed();
+    let commits: Vec<_> = reader.map(|x| x.unwrap()).collect();
+
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0].message, "3".into());
+    assert_eq!(commits[1].message, "4".into());
+  }
 }
@@ -8,6 +8,9 @@ pub enum GitError {
   #[error("git: no head found")]
   NoHead,
 
+  #[error("git: head has moved")]
+  HeadMoved,
+
   #[error("git2 error:{0}")]
   Git2(#[from] git2::Error),
 
@@ -10,6 +10,14 @@ pub fn get_head(repo: &Repository) -> GitResult<Oid> {
   }
 }
 
+pub fn find_head(repo: &Repository) -> GitResult<Option<Oid>> {
+  match repo.head() {
+    Ok(head) => Ok(head.target()),
+    Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => Ok(None),
+    Err(e) => Err(e.into()),
+  }
+}
+
 pub fn get_head_commit(repo: &Repository) -> GitResult<CommitInfo> {
   let head = get_head(repo)?;
   let commit = repo.find_commit(head).map(CommitInfo::from)?;
@@ -57,4 +65,12 @@ mod tests {
 
     assert_eq!(head_commit.message, "initial".into());
   }
+
+  #[test]
+  fn should_find_no_head_on_empty_repository() {
+    let fixture = FixtureRepository::setup();
+    let repo = Repository::open(&fixture.path).unwrap();
+
+    assert_eq!(find_head(&repo).unwrap(), None);
+  }
 }